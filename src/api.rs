@@ -0,0 +1,418 @@
+use std::fs;
+use std::path::Path;
+use itertools::{izip, repeat_n, Itertools};
+use rand::seq::SliceRandom;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::backend::{Backend, ComparisonType};
+use crate::common::ciphertexts::{Decision, EncryptedCandidate, EncryptedDecision, EncryptedIdentification, EncryptedProbe, EncryptedTemplate};
+use crate::common::config::Config;
+use crate::common::data;
+use crate::common::error::{Error, Result};
+use crate::common::keygen::genkeys_multibit;
+use crate::common::keys::{ClientKey, ServerKey};
+use crate::common::reference::{run_reference, ReferenceTrace};
+use crate::common::tfhe_utils::{concat_glwe_lists, get_params_multi_bit_gpu, repeat_lwe_list, short_params};
+use crate::common::util::luts_to_closures;
+
+/// Mated (same subject) or non-mated comparison.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairKind {
+    Genuine,
+    Impostor,
+}
+
+/// Quantized probe and template of two samples of the feature vector file, with their row indices.
+pub struct SamplePair {
+    pub kind: PairKind,
+    pub probe_index: usize,
+    pub template_index: usize,
+    pub probe: Vec<u64>,
+    pub template: Vec<u64>,
+}
+
+/// A dataset loaded from disk: the offset HELR tables and the LUT layout derived from them.
+pub struct Dataset {
+    pub config: Config,
+    offset: i64,
+    helr_tables: Vec<Vec<Vec<u64>>>,
+    decomp_base: u64,
+    lut_output_indices: Vec<u64>,
+    decomp_lengths: Vec<usize>,
+    sum_block_len: usize,
+    params: MultiBitPBSParameters,
+}
+
+impl Dataset {
+    /// Reads the HELR tables of `config` and derives the decomposition base, LUT layout and parameters.
+    /// A decomposition base given in the manifest takes precedence over the smallest possible one.
+    pub fn load(config: Config) -> Result<Self> {
+        let (offset, helr_tables) = data::load_and_offset_helr_tables(&config)?;
+        data::check_qbins(&config, &helr_tables)?;
+        let decomp_base = data::get_decomp_base(&config, &helr_tables)?;
+        let (lut_output_indices, decomp_lengths, sum_block_len) = data::get_lut_output_indices(&helr_tables, decomp_base)?;
+        let params = get_params_multi_bit_gpu(decomp_base)?;
+
+        Ok(Dataset {
+            config,
+            offset,
+            helr_tables,
+            decomp_base,
+            lut_output_indices,
+            decomp_lengths,
+            sum_block_len,
+            params,
+        })
+    }
+
+    /// Quantized probe and template for the feature vector at `index`. The probe is the template
+    /// with a little uniform noise, i.e. an almost perfect genuine match; `select_pairs` gives
+    /// real mated and non-mated comparisons.
+    pub fn sample(&self, index: usize) -> Result<(Vec<u64>, Vec<u64>)> {
+        data::get_probe_and_template(&self.config, index)
+    }
+
+    /// Quantized probe and template of two real samples, without synthetic noise.
+    pub fn pair(&self, probe_index: usize, template_index: usize) -> Result<SamplePair> {
+        let (labels, features) = data::read_labelled_samples(&self.config)?;
+        self.make_pair(&labels, &features, probe_index, template_index)
+    }
+
+    /// Up to `count` random pairs of the given kind. Genuine pairs are two different samples of
+    /// the same subject, impostor pairs samples of two different subjects. Fails if the dataset
+    /// holds fewer impostor pairs than requested; there may be fewer genuine ones, datasets with
+    /// a single sample per subject have none at all.
+    pub fn select_pairs(&self, kind: PairKind, count: usize) -> Result<Vec<SamplePair>> {
+        let (labels, features) = data::read_labelled_samples(&self.config)?;
+        let indices = match kind {
+            PairKind::Genuine => {
+                let mut pairs = data::genuine_pairs(&labels);
+                pairs.shuffle(&mut rand::rng());
+                pairs.truncate(count);
+                pairs
+            }
+            PairKind::Impostor => data::random_impostor_pairs(&labels, count)?,
+        };
+        indices.into_iter()
+            .map(|(probe_index, template_index)| self.make_pair(&labels, &features, probe_index, template_index))
+            .collect()
+    }
+
+    fn make_pair(&self, labels: &[String], features: &[Vec<f64>], probe_index: usize, template_index: usize) -> Result<SamplePair> {
+        let (probe, template) = data::get_pair(&self.config, features, probe_index, template_index)?;
+        let kind = if labels[probe_index] == labels[template_index] { PairKind::Genuine } else { PairKind::Impostor };
+        Ok(SamplePair { kind, probe_index, template_index, probe, template })
+    }
+
+    pub fn decomp_base(&self) -> u64 {
+        self.decomp_base
+    }
+
+    pub fn params(&self) -> MultiBitPBSParameters {
+        self.params
+    }
+
+    /// Reject and accept thresholds shifted by the table offsets, as compared against the encrypted score.
+    pub fn thresholds(&self) -> (u64, u64) {
+        (self.offset_threshold(self.config.reject_threshold), self.offset_threshold(self.config.accept_threshold))
+    }
+
+    /// Shifts a threshold on the HELR score by the table offsets. Thresholds below the lowest
+    /// possible score are met by every probe and map to 0.
+    pub fn offset_threshold(&self, threshold: i64) -> u64 {
+        data::offset_threshold(threshold, self.offset)
+    }
+
+    /// Runs the protocol in the clear, for checking encrypted results.
+    pub fn reference(&self, probe: &[u64], template: &[u64]) -> Result<ReferenceTrace> {
+        let luts = data::make_row_based_luts(&self.helr_tables, template, self.decomp_base)?;
+        run_reference(&luts, &self.repeat_probe(probe), &self.lut_output_indices, self.sum_block_len, self.decomp_base, self.thresholds())
+    }
+
+    fn repeat_probe(&self, probe: &[u64]) -> Vec<u64> {
+        izip!(probe.iter(), self.decomp_lengths.iter()).map(|(p, len)| repeat_n(*p, *len)).flatten().collect_vec()
+    }
+}
+
+/// Generates a fresh client key and the matching server key for the parameters of `dataset`.
+/// Only the server key is meant to leave the client.
+pub fn generate_keys(dataset: &Dataset) -> (ClientKey, ServerKey) {
+    genkeys_multibit(dataset.params())
+}
+
+fn key_paths(dataset: &Dataset, dir: &str) -> (String, String) {
+    (format!("{}/{}_client.key", dir, dataset.config.data_set_name), format!("{}/{}_server.key", dir, dataset.config.data_set_name))
+}
+
+/// Loads the keys of `dataset` from `dir`, generating and saving them there if neither key file
+/// exists. Fails if only one of them exists rather than replacing the other: the templates
+/// enrolled under a client key can not be decrypted with a new one. Keys stored for another
+/// parameter set are rejected.
+pub fn load_or_generate_keys(dataset: &Dataset, dir: &str) -> Result<(ClientKey, ServerKey)> {
+    let (client_key_path, server_key_path) = key_paths(dataset, dir);
+    match (Path::new(&client_key_path).exists(), Path::new(&server_key_path).exists()) {
+        (true, true) => {
+            let client_key = ClientKey::load(&client_key_path, dataset.params())?;
+            let server_key = ServerKey::load(&server_key_path, dataset.params())?;
+            Ok((client_key, server_key))
+        }
+        (false, false) => save_new_keys(dataset, dir),
+        (true, false) => Err(Error::format(&server_key_path, &format!("missing, while the client key {} exists", client_key_path))),
+        (false, true) => Err(Error::format(&client_key_path, &format!("missing, while the server key {} exists", server_key_path))),
+    }
+}
+
+/// Generates new keys for `dataset` and saves them to `dir`, replacing existing key files. The
+/// templates enrolled under the replaced client key can no longer be decrypted.
+pub fn replace_keys(dataset: &Dataset, dir: &str) -> Result<(ClientKey, ServerKey)> {
+    let (client_key_path, server_key_path) = key_paths(dataset, dir);
+    for path in [client_key_path, server_key_path] {
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(Error::io(&path, err)),
+            _ => {}
+        }
+    }
+    save_new_keys(dataset, dir)
+}
+
+fn save_new_keys(dataset: &Dataset, dir: &str) -> Result<(ClientKey, ServerKey)> {
+    let (client_key_path, server_key_path) = key_paths(dataset, dir);
+    fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+    let (client_key, server_key) = generate_keys(dataset);
+    client_key.save(&client_key_path)?;
+    server_key.save(&server_key_path)?;
+    Ok((client_key, server_key))
+}
+
+/// Builds the row based LUTs of a quantized template and encrypts them under the GLWE key,
+/// together with the reject and accept thresholds of the dataset.
+pub fn enroll(client_key: &mut ClientKey, dataset: &Dataset, template: &[u64]) -> Result<EncryptedTemplate> {
+    enroll_with_thresholds(client_key, dataset, template, dataset.config.reject_threshold, dataset.config.accept_threshold)
+}
+
+/// Like `enroll`, with subject specific reject and accept thresholds on the HELR score. The
+/// thresholds are encrypted as well, the server never learns them.
+pub fn enroll_with_thresholds(client_key: &mut ClientKey, dataset: &Dataset, template: &[u64], reject_threshold: i64, accept_threshold: i64) -> Result<EncryptedTemplate> {
+    if reject_threshold > accept_threshold {
+        return Err(Error::UnsupportedParameters(format!("reject threshold {} is above the accept threshold {}", reject_threshold, accept_threshold)));
+    }
+    let reject_threshold = dataset.offset_threshold(reject_threshold);
+    let accept_threshold = dataset.offset_threshold(accept_threshold);
+    let capacity = (dataset.params.message_modulus.0 as u64).checked_pow(dataset.sum_block_len as u32).unwrap_or(u64::MAX);
+    if accept_threshold >= capacity {
+        return Err(Error::UnsupportedParameters(format!("threshold {} does not fit in {} sum blocks", accept_threshold, dataset.sum_block_len)));
+    }
+
+    let luts = data::make_row_based_luts(&dataset.helr_tables, template, dataset.decomp_base)?;
+    let functions = luts_to_closures(luts.into_iter().flatten().collect_vec());
+    let glwe_luts = client_key.encrypt_luts(&functions);
+
+    Ok(EncryptedTemplate {
+        dataset: dataset.config.data_set_name.to_string(),
+        decomp_base: dataset.decomp_base,
+        decomp_lengths: dataset.decomp_lengths.clone(),
+        lut_output_indices: dataset.lut_output_indices.clone(),
+        sum_block_len: dataset.sum_block_len,
+        reject_threshold: client_key.encrypt_radix(reject_threshold, dataset.sum_block_len),
+        accept_threshold: client_key.encrypt_radix(accept_threshold, dataset.sum_block_len),
+        luts: glwe_luts,
+    })
+}
+
+/// Encrypts a quantized probe under the LWE key.
+pub fn encrypt_probe(client_key: &mut ClientKey, dataset: &Dataset, probe: &[u64]) -> Result<EncryptedProbe> {
+    if probe.len() != dataset.decomp_lengths.len() {
+        return Err(Error::shape("probe features", dataset.decomp_lengths.len(), probe.len()));
+    }
+    let cts = client_key.encrypt_list(&dataset.repeat_probe(probe));
+    Ok(EncryptedProbe {
+        dataset: dataset.config.data_set_name.to_string(),
+        decomp_base: dataset.decomp_base,
+        decomp_lengths: dataset.decomp_lengths.clone(),
+        cts,
+    })
+}
+
+/// Evaluates the encrypted template on the encrypted probe and compares the score to the reject and
+/// accept thresholds.
+pub fn verify<B: Backend>(backend: &B, evaluation_key: &B::EvaluationKey, template: &EncryptedTemplate, probe: &EncryptedProbe) -> Result<EncryptedDecision> {
+    let (_, decisions) = evaluate_gallery(backend, evaluation_key, std::slice::from_ref(template), probe)?;
+    let decision = decisions.first().ok_or_else(|| Error::shape("decisions", 1, 0))?;
+    Ok(EncryptedDecision {
+        decomp_base: template.decomp_base,
+        ct: backend.lwe_to_host(decision),
+    })
+}
+
+/// Evaluates every template on the encrypted probe, returning one encrypted decision per template
+/// and the encrypted index and decision of the best match. The PBS of all templates run as a
+/// single batch, the sums and comparisons per template. The best match is selected under
+/// encryption by the decisions alone: accept before step-up before reject, ties going to the
+/// lower index.
+pub fn identify<B: Backend>(backend: &B, evaluation_key: &B::EvaluationKey, templates: &[EncryptedTemplate], probe: &EncryptedProbe) -> Result<EncryptedIdentification> {
+    let (_, decisions) = evaluate_gallery(backend, evaluation_key, templates, probe)?;
+    let decomp_base = templates[0].decomp_base;
+    let (index, decision) = backend.top_k(&decisions, &decisions, 1, evaluation_key, templates[0].params()?)?
+        .pop()
+        .ok_or_else(|| Error::shape("best matches", 1, 0))?;
+    let decisions = izip!(decisions, templates)
+        .map(|(decision, template)| EncryptedDecision {
+            decomp_base: template.decomp_base,
+            ct: backend.lwe_to_host(&decision),
+        })
+        .collect_vec();
+    Ok(EncryptedIdentification {
+        decisions,
+        best: EncryptedCandidate {
+            index: backend.lwe_to_host(&index),
+            decision: EncryptedDecision {
+                decomp_base,
+                ct: backend.lwe_to_host(&decision),
+            },
+        },
+    })
+}
+
+/// Ranks the templates by their encrypted score and returns the encrypted index and decision of the
+/// `k` best ones, best first. The scores themselves stay on the server.
+pub fn rank<B: Backend>(backend: &B, evaluation_key: &B::EvaluationKey, templates: &[EncryptedTemplate], probe: &EncryptedProbe, k: usize) -> Result<Vec<EncryptedCandidate>> {
+    if k == 0 || k > templates.len() {
+        return Err(Error::shape("ranked candidates", templates.len(), k));
+    }
+    let (sums, decisions) = evaluate_gallery(backend, evaluation_key, templates, probe)?;
+    let decomp_base = templates[0].decomp_base;
+    let candidates = backend.top_k(&sums, &decisions, k, evaluation_key, templates[0].params()?)?
+        .iter()
+        .map(|(index, decision)| EncryptedCandidate {
+            index: backend.lwe_to_host(index),
+            decision: EncryptedDecision {
+                decomp_base,
+                ct: backend.lwe_to_host(decision),
+            },
+        })
+        .collect_vec();
+    Ok(candidates)
+}
+
+// Radix sums and decisions of the templates of a gallery, in template order
+type GalleryResult<L> = (Vec<L>, Vec<L>);
+
+// Runs the PBS of all templates as one batch, then the sum and threshold comparisons of every
+// template, returning the radix sums and the decisions. The decision block is the sum of the two
+// comparison bits, i.e. the number of thresholds reached.
+fn evaluate_gallery<B: Backend>(backend: &B, evaluation_key: &B::EvaluationKey, templates: &[EncryptedTemplate], probe: &EncryptedProbe) -> Result<GalleryResult<B::LweList>> {
+    let first = templates.first().ok_or_else(|| Error::shape("enrolled templates", 1, 0))?;
+    probe.validate()?;
+    for template in templates {
+        template.validate()?;
+        probe.check_compatible(template)?;
+        if template.sum_block_len != first.sum_block_len {
+            return Err(Error::shape("template sum block length", first.sum_block_len, template.sum_block_len));
+        }
+    }
+    let params = first.params()?;
+    let short_params = short_params(params);
+
+    let num_subjects = templates.len();
+    let num_cts = first.num_tables();
+    let pbs_out_blocks = num_cts * first.sum_block_len;
+    let lwe_size = params.glwe_dimension.to_equivalent_lwe_dimension(params.polynomial_size).to_lwe_size();
+
+    // Template `s` reads its own copy of the probe and writes to its own range of output blocks
+    let cts_in = repeat_lwe_list(&probe.cts, num_subjects);
+    let luts = concat_glwe_lists(&templates.iter().map(|template| &template.luts).collect_vec())?;
+    let output_indices = templates.iter().enumerate()
+        .flat_map(|(s, template)| template.lut_output_indices.iter().map(move |idx| idx + (s * pbs_out_blocks) as u64))
+        .collect_vec();
+
+    let pbs_res = backend.encrypted_pbs(
+        backend.lwe_to_backend(&cts_in),
+        backend.glwe_to_backend(&luts),
+        output_indices.clone(),
+        output_indices.len(),
+        num_subjects * pbs_out_blocks,
+        lwe_size,
+        &short_params,
+        evaluation_key,
+    );
+
+    let evaluated = izip!(backend.split_lwe(&pbs_res, pbs_out_blocks), templates)
+        .map(|(mut subject_res, template)| -> Result<_> {
+            let sum_res = backend.sum(
+                &mut subject_res,
+                template.sum_block_len,
+                num_cts,
+                evaluation_key,
+                params,
+            )?;
+
+            let reject_res = backend.comparison_ct(
+                &sum_res,
+                &backend.lwe_to_backend(&template.reject_threshold),
+                evaluation_key,
+                ComparisonType::GE,
+                params,
+            );
+            let mut comp_res = backend.comparison_ct(
+                &sum_res,
+                &backend.lwe_to_backend(&template.accept_threshold),
+                evaluation_key,
+                ComparisonType::GE,
+                params,
+            );
+            backend.add_assign(&mut comp_res, &reject_res);
+            Ok((sum_res, comp_res))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(evaluated.into_iter().unzip())
+}
+
+/// Decrypts the outcome of a verification. A decrypted value that is not a decision, e.g. under
+/// the wrong key, is an error rather than a reject or an accept.
+pub fn decrypt_decision(client_key: &ClientKey, decision: &EncryptedDecision) -> Result<Decision> {
+    let result = client_key.decrypt_list(decision.ct.as_view());
+    Decision::from_count(*result.first().ok_or_else(|| Error::shape("decision blocks", 1, 0))?)
+}
+
+/// Decrypts the best match returned by `identify`: the template index and its outcome, None if
+/// every template rejected the probe. The per-template decisions stay encrypted.
+pub fn decrypt_match(client_key: &ClientKey, identification: &EncryptedIdentification) -> Result<Option<(usize, Decision)>> {
+    let (index, decision) = decrypt_candidate(client_key, &identification.best)?;
+    Ok((decision != Decision::Reject).then_some((index, decision)))
+}
+
+/// Decrypts a candidate returned by `rank`: the template index and its outcome.
+pub fn decrypt_candidate(client_key: &ClientKey, candidate: &EncryptedCandidate) -> Result<(usize, Decision)> {
+    let message_modulus = client_key.params().message_modulus.0 as u64;
+    let digits = client_key.decrypt_list(candidate.index.as_view());
+    let index = digits.iter().rev().fold(0, |acc, digit| acc * message_modulus + digit);
+    Ok((index as usize, decrypt_decision(client_key, &candidate.decision)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::data::tests::{fixture_config, FIXTURE_BINS, FIXTURE_LABELS};
+
+    fn check_pair(pair: &SamplePair) {
+        let genuine = FIXTURE_LABELS[pair.probe_index] == FIXTURE_LABELS[pair.template_index];
+        assert_eq!(pair.kind, if genuine { PairKind::Genuine } else { PairKind::Impostor });
+        assert_ne!(pair.probe_index, pair.template_index);
+        assert_eq!(pair.probe, FIXTURE_BINS[pair.probe_index]);
+        assert_eq!(pair.template, FIXTURE_BINS[pair.template_index]);
+    }
+
+    #[test]
+    fn select_pairs_on_blank_lines() {
+        let dataset = Dataset::load(fixture_config("select_pairs")).unwrap();
+        let genuine = dataset.select_pairs(PairKind::Genuine, 10).unwrap();
+        assert_eq!(genuine.len(), 4);
+        genuine.iter().for_each(check_pair);
+        let impostor = dataset.select_pairs(PairKind::Impostor, 16).unwrap();
+        assert_eq!(impostor.len(), 16);
+        impostor.iter().for_each(check_pair);
+        assert!(dataset.select_pairs(PairKind::Impostor, 17).is_err());
+        for (index, bins) in FIXTURE_BINS.iter().enumerate() {
+            assert_eq!(&dataset.sample(index).unwrap().1, bins);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use encrypted_biometrics::common::audit::verify_log;
+use encrypted_biometrics::common::util::arg_value;
+use std::process::exit;
+
+const USAGE: &str = "usage: audit verify --log <file>";
+
+// Checks the hash chain of an audit log against its head, exits with 1 if the log was modified or
+// truncated
+fn main() {
+    let command = std::env::args().nth(1).unwrap_or_default();
+    let (Some(path), "verify") = (arg_value("--log"), command.as_str()) else {
+        eprintln!("{}", USAGE);
+        exit(2)
+    };
+    match verify_log(&path) {
+        Ok(head) => println!("{}: {} entries, chain intact, head {}", path, head.entries, head.hash),
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    }
+}
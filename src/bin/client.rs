@@ -0,0 +1,128 @@
+use encrypted_biometrics::common::config::{config_by_name, DEFAULT_MANIFEST};
+use encrypted_biometrics::common::util::arg_value;
+use encrypted_biometrics::net::client::Client;
+use encrypted_biometrics::net::protocol::DEFAULT_ADDR;
+use encrypted_biometrics::net::session::load_psk;
+use encrypted_biometrics::{decrypt_candidate, decrypt_decision, decrypt_match, encrypt_probe, enroll_with_thresholds, load_or_generate_keys, replace_keys, ClientKey, Dataset, Result, TemplateStore};
+use std::process::exit;
+
+const USAGE: &str = "usage (all commands accept --manifest <file>):
+  client enroll --dataset <name> --keys <dir> --store <dir> --subject <id> --index <n> [--reject <t> --accept <t>] [--force-keys]
+  client verify --dataset <name> --keys <dir> --subject <id> --index <n> --psk <file> [--addr <host:port>]
+  client identify --dataset <name> --keys <dir> --index <n> --psk <file> [--top <k>] [--addr <host:port>]";
+
+fn required(name: &str) -> String {
+    arg_value(name).unwrap_or_else(|| {
+        eprintln!("missing {}\n{}", name, USAGE);
+        exit(2)
+    })
+}
+
+fn index() -> usize {
+    required("--index").parse().unwrap_or_else(|_| {
+        eprintln!("--index must be a number\n{}", USAGE);
+        exit(2)
+    })
+}
+
+fn threshold_arg(name: &str, default: i64) -> i64 {
+    arg_value(name).map_or(default, |t| t.parse().unwrap_or_else(|_| {
+        eprintln!("{} must be a number\n{}", name, USAGE);
+        exit(2)
+    }))
+}
+
+// Enrollment station: generates the keys on first use and stores the encrypted template. The
+// thresholds default to the ones of the dataset. --force-keys replaces existing keys, the
+// templates enrolled before can then no longer be decrypted.
+fn run_enroll(dataset: Dataset) -> Result<()> {
+    let keys_dir = required("--keys");
+    let (mut client_key, _) = if std::env::args().any(|arg| arg == "--force-keys") {
+        replace_keys(&dataset, &keys_dir)?
+    } else {
+        load_or_generate_keys(&dataset, &keys_dir)?
+    };
+    let store = TemplateStore::open(&required("--store"))?;
+    let subject_id = required("--subject");
+    let reject_threshold = threshold_arg("--reject", dataset.config.reject_threshold);
+    let accept_threshold = threshold_arg("--accept", dataset.config.accept_threshold);
+
+    let (_, template) = dataset.sample(index())?;
+    let encrypted_template = enroll_with_thresholds(&mut client_key, &dataset, &template, reject_threshold, accept_threshold)?;
+    store.insert(&subject_id, &encrypted_template)?;
+    println!("Enrolled {}", subject_id);
+    Ok(())
+}
+
+fn load_client_key(dataset: &Dataset) -> Result<ClientKey> {
+    let keys_dir = required("--keys");
+    ClientKey::load(&format!("{}/{}_client.key", keys_dir, dataset.config.data_set_name), dataset.params())
+}
+
+fn run_verify(dataset: Dataset) -> Result<()> {
+    let mut client_key = load_client_key(&dataset)?;
+    let subject_id = required("--subject");
+    let addr = arg_value("--addr").unwrap_or_else(|| DEFAULT_ADDR.to_string());
+
+    let (probe, _) = dataset.sample(index())?;
+    let encrypted_probe = encrypt_probe(&mut client_key, &dataset, &probe)?;
+
+    let psk = load_psk(&required("--psk"))?;
+    let mut client = Client::connect(&addr, &dataset.config.data_set_name, dataset.params(), &psk)?;
+    let decision = decrypt_decision(&client_key, &client.verify(&subject_id, encrypted_probe)?)?;
+    println!("{}: {}", subject_id, decision);
+    client.report_decision(decision)
+}
+
+// Only the index and decision of the best match are decrypted, the per subject decisions are not.
+// With --top the server ranks the gallery and only the best k candidates are returned.
+fn run_identify(dataset: Dataset) -> Result<()> {
+    let mut client_key = load_client_key(&dataset)?;
+    let addr = arg_value("--addr").unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let top = arg_value("--top").map(|k| k.parse::<u32>().unwrap_or_else(|_| {
+        eprintln!("--top must be a number\n{}", USAGE);
+        exit(2)
+    }));
+
+    let (probe, _) = dataset.sample(index())?;
+    let encrypted_probe = encrypt_probe(&mut client_key, &dataset, &probe)?;
+
+    let psk = load_psk(&required("--psk"))?;
+    let mut client = Client::connect(&addr, &dataset.config.data_set_name, dataset.params(), &psk)?;
+    if let Some(k) = top {
+        let (subject_ids, candidates) = client.rank(encrypted_probe, k)?;
+        for (rank, candidate) in candidates.iter().enumerate() {
+            let (index, decision) = decrypt_candidate(&client_key, candidate)?;
+            let subject_id = subject_ids.get(index).map(String::as_str).unwrap_or("?");
+            println!("{}. {}: {}", rank + 1, subject_id, decision);
+        }
+        return Ok(());
+    }
+    let (subject_ids, identification) = client.identify(encrypted_probe)?;
+    match decrypt_match(&client_key, &identification)? {
+        Some((index, decision)) => println!("{}: {}", subject_ids.get(index).map(String::as_str).unwrap_or("?"), decision),
+        None => println!("no match"),
+    }
+    Ok(())
+}
+
+fn main() {
+    let command = std::env::args().nth(1).unwrap_or_default();
+    let dataset_name = required("--dataset");
+    let manifest_path = arg_value("--manifest").unwrap_or_else(|| DEFAULT_MANIFEST.to_string());
+    let run = match command.as_str() {
+        "enroll" => run_enroll,
+        "verify" => run_verify,
+        "identify" => run_identify,
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2)
+        }
+    };
+
+    let result = config_by_name(&manifest_path, &dataset_name).and_then(Dataset::load).and_then(run);
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,56 @@
+use encrypted_biometrics::common::data::read_labelled_csv;
+use encrypted_biometrics::common::qbins::{check_bin_count, generate_qbins, write_qbins, BinSource};
+use encrypted_biometrics::common::util::arg_value;
+use encrypted_biometrics::Result;
+use std::process::exit;
+
+const USAGE: &str = "usage: qbins --bins <n> --out <file> [--source gaussian|empirical] [--features <csv> [--label-column <n>]] [--per-feature]";
+
+fn required(name: &str) -> String {
+    arg_value(name).unwrap_or_else(|| {
+        eprintln!("missing {}\n{}", name, USAGE);
+        exit(2)
+    })
+}
+
+// Writes the boundaries of equiprobable quantization bins. Empirical and per feature bins are
+// computed from the training vectors of --features.
+fn run() -> Result<()> {
+    let num_bins: usize = required("--bins").parse().unwrap_or_else(|_| {
+        eprintln!("--bins must be a number\n{}", USAGE);
+        exit(2)
+    });
+    let out = required("--out");
+    let source: BinSource = arg_value("--source").map_or(BinSource::Gaussian, |name| name.parse().unwrap_or_else(|err: String| {
+        eprintln!("{}\n{}", err, USAGE);
+        exit(2)
+    }));
+    let per_feature = std::env::args().any(|arg| arg == "--per-feature");
+
+    let features = match arg_value("--features") {
+        Some(path) => {
+            let label_column = arg_value("--label-column").map_or(0, |column| column.parse().unwrap_or_else(|_| {
+                eprintln!("--label-column must be a number\n{}", USAGE);
+                exit(2)
+            }));
+            read_labelled_csv(&path, label_column)?.1
+        }
+        None if source == BinSource::Gaussian && !per_feature => Vec::new(),
+        None => {
+            eprintln!("empirical and per feature bins need --features\n{}", USAGE);
+            exit(2)
+        }
+    };
+
+    let rows = generate_qbins(&features, num_bins, source, per_feature)?;
+    write_qbins(&out, &rows)?;
+    println!("Wrote {} row(s) of {} boundaries to {}, tables of dimension {} use decomposition base {}", rows.len(), num_bins - 1, out, num_bins, check_bin_count(num_bins)?);
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
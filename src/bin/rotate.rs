@@ -0,0 +1,47 @@
+use encrypted_biometrics::common::config::{config_by_name, DEFAULT_MANIFEST};
+use encrypted_biometrics::common::rotation::rotate_store;
+use encrypted_biometrics::common::util::arg_value;
+use encrypted_biometrics::{load_or_generate_keys, ClientKey, Dataset, Result, TemplateStore};
+use std::process::exit;
+
+const USAGE: &str = "usage: rotate --dataset <name> [--manifest <file>] --old-keys <dir> --new-keys <dir> --store <dir> --target <dir>";
+
+fn required(name: &str) -> String {
+    arg_value(name).unwrap_or_else(|| {
+        eprintln!("missing {}\n{}", name, USAGE);
+        exit(2)
+    })
+}
+
+// Re-encrypts the templates of `--store` under the keys in `--new-keys` (generated on first use)
+// and writes them to `--target`. Rerunning after an interruption resumes the migration.
+fn run() -> Result<()> {
+    let dataset_name = required("--dataset");
+    let manifest_path = arg_value("--manifest").unwrap_or_else(|| DEFAULT_MANIFEST.to_string());
+    let dataset = Dataset::load(config_by_name(&manifest_path, &dataset_name)?)?;
+    let dataset_name = &dataset.config.data_set_name;
+
+    let old_key = ClientKey::load(&format!("{}/{}_client.key", required("--old-keys"), dataset_name), dataset.params())?;
+    let (mut new_key, _) = load_or_generate_keys(&dataset, &required("--new-keys"))?;
+    let source = TemplateStore::open(&required("--store"))?;
+    let target = TemplateStore::open(&required("--target"))?;
+
+    let report = rotate_store(dataset_name, &old_key, &mut new_key, &source, &target)?;
+    for subject_id in &report.migrated {
+        println!("Migrated {}", subject_id);
+    }
+    println!(
+        "{} templates migrated and verified, {} already migrated, {} of other datasets skipped",
+        report.migrated.len(),
+        report.already_migrated.len(),
+        report.skipped.len()
+    );
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,75 @@
+use encrypted_biometrics::common::audit::AuditLog;
+use encrypted_biometrics::common::config::{config_by_name, DEFAULT_MANIFEST};
+use encrypted_biometrics::common::util::arg_value;
+use encrypted_biometrics::net::protocol::DEFAULT_ADDR;
+use encrypted_biometrics::net::rate_limit::{RateLimitPolicy, RateLimiter, SystemClock};
+use encrypted_biometrics::net::server::Server;
+use encrypted_biometrics::net::session::load_psk;
+#[cfg(feature = "cpu")]
+use encrypted_biometrics::cpu::CpuBackend;
+#[cfg(feature = "gpu")]
+use encrypted_biometrics::gpu::GpuBackend;
+use encrypted_biometrics::{Backend, BackendKind, Dataset, Error, Result, ServerKey, TemplateStore};
+use std::process::exit;
+
+const USAGE: &str = "usage: server --dataset <name> [--manifest <file>] --keys <dir> --store <dir> --psk <file> [--audit <file>] [--rate-policy <file>] [--rate-state <file>] [--addr <host:port>] [--backend cpu|gpu]";
+
+fn required(name: &str) -> String {
+    arg_value(name).unwrap_or_else(|| {
+        eprintln!("missing {}\n{}", name, USAGE);
+        exit(2)
+    })
+}
+
+fn serve<B: Backend>(server: Server<B>, audit_log: Option<AuditLog>, rate_limiter: RateLimiter, addr: &str) -> Result<()> {
+    let server = server.with_rate_limiter(rate_limiter);
+    match audit_log {
+        Some(audit_log) => server.with_audit_log(audit_log).serve(addr),
+        None => server.serve(addr),
+    }
+}
+
+fn run() -> Result<()> {
+    let dataset_name = required("--dataset");
+    let keys_dir = required("--keys");
+    let store_dir = required("--store");
+    let addr = arg_value("--addr").unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let kind = match arg_value("--backend") {
+        Some(name) => name.parse().unwrap_or_else(|err: String| {
+            eprintln!("{}", err);
+            exit(2)
+        }),
+        None if cfg!(feature = "gpu") => BackendKind::Gpu,
+        None => BackendKind::Cpu,
+    };
+
+    let manifest_path = arg_value("--manifest").unwrap_or_else(|| DEFAULT_MANIFEST.to_string());
+    let dataset = Dataset::load(config_by_name(&manifest_path, &dataset_name)?)?;
+    let dataset_name = &dataset.config.data_set_name;
+    // The server only ever loads the server key
+    let server_key = ServerKey::load(&format!("{}/{}_server.key", keys_dir, dataset_name), dataset.params())?;
+    let store = TemplateStore::open(&store_dir)?;
+    let psk = load_psk(&required("--psk"))?;
+
+    let audit_log = arg_value("--audit").map(|path| AuditLog::open(&path)).transpose()?;
+    // Rate limiting is always on, with the default policy unless a policy file is given
+    let policy = arg_value("--rate-policy").map(|path| RateLimitPolicy::load(&path)).transpose()?.unwrap_or_default();
+    let state_path = arg_value("--rate-state").unwrap_or_else(|| format!("{}/rate_limit.json", store_dir));
+    let rate_limiter = RateLimiter::open(policy, Box::new(SystemClock), &state_path)?;
+
+    match kind {
+        #[cfg(feature = "gpu")]
+        BackendKind::Gpu => serve(Server::new(GpuBackend::new(0), &server_key, dataset_name, store, psk), audit_log, rate_limiter, &addr),
+        #[cfg(feature = "cpu")]
+        BackendKind::Cpu => serve(Server::new(CpuBackend, &server_key, dataset_name, store, psk), audit_log, rate_limiter, &addr),
+        #[allow(unreachable_patterns)]
+        _ => Err(Error::BackendNotEnabled(format!("{:?}", kind).to_lowercase())),
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
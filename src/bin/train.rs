@@ -0,0 +1,70 @@
+use encrypted_biometrics::common::data::{read_labelled_csv, read_qbins};
+use encrypted_biometrics::common::training::{train_helr_tables, train_helr_tables_with_qbins, write_tables, TrainingOptions};
+use encrypted_biometrics::common::util::arg_value;
+use encrypted_biometrics::{Error, Result};
+use std::fs;
+use std::process::exit;
+
+const USAGE: &str = "usage: train --features <csv> --name <dataset> --out <dir> [--label-column <n>] [--bins <n>] [--source gaussian|empirical] [--per-feature] [--qbins <file>] [--scale <f>] [--prefix <prefix>]";
+
+fn required(name: &str) -> String {
+    arg_value(name).unwrap_or_else(|| {
+        eprintln!("missing {}\n{}", name, USAGE);
+        exit(2)
+    })
+}
+
+fn number_arg<T: std::str::FromStr>(name: &str, default: T) -> T {
+    arg_value(name).map_or(default, |value| value.parse().unwrap_or_else(|_| {
+        eprintln!("{} must be a number\n{}", name, USAGE);
+        exit(2)
+    }))
+}
+
+fn absolute(path: &str) -> Result<String> {
+    fs::canonicalize(path).map(|path| path.to_string_lossy().to_string()).map_err(|err| Error::io(path, err))
+}
+
+// Trains HELR tables from labelled feature vectors and prints the manifest entry for them
+fn run() -> Result<()> {
+    let features_path = required("--features");
+    let name = required("--name");
+    let out_dir = required("--out");
+    let prefix = arg_value("--prefix").unwrap_or_else(|| "HELR".to_string());
+    let defaults = TrainingOptions::default();
+    let options = TrainingOptions {
+        num_bins: number_arg("--bins", defaults.num_bins),
+        source: arg_value("--source").map_or(defaults.source, |name| name.parse().unwrap_or_else(|err: String| {
+            eprintln!("{}\n{}", err, USAGE);
+            exit(2)
+        })),
+        per_feature: std::env::args().any(|arg| arg == "--per-feature"),
+        scale: number_arg("--scale", defaults.scale),
+    };
+
+    let (labels, features) = read_labelled_csv(&features_path, number_arg("--label-column", 0))?;
+    // --qbins trains on existing bins, e.g. with a different bin count per feature
+    let trained = match arg_value("--qbins") {
+        Some(path) => train_helr_tables_with_qbins(&labels, &features, read_qbins(&path)?, options.scale)?,
+        None => train_helr_tables(&labels, &features, &options)?,
+    };
+    write_tables(&trained, &out_dir, &prefix, &name)?;
+
+    for (idx, model) in trained.models.iter().enumerate() {
+        println!("feature {}: mean {:.4}, std {:.4}, between {:.4}, within {:.4}", idx, model.mean, model.std_dev, model.between, model.within);
+    }
+    let dimensions = trained.tables.iter().map(|table| table.len()).collect::<Vec<_>>();
+    println!("Wrote {} tables of dimension {} to {} to {}", trained.tables.len(), dimensions.iter().min().unwrap_or(&0), dimensions.iter().max().unwrap_or(&0), out_dir);
+    // Absolute, as the manifest resolves relative paths against its own directory
+    let features_path = absolute(&features_path)?;
+    let out_dir = absolute(&out_dir)?;
+    println!("\n[[dataset]]\nname = \"{}\"\nfeatures = \"{}\"\ntables = \"{}\"\ntable_prefix = \"{}\"\nnum_tables = {}\nthreshold = 0", name, features_path, out_dir, prefix, trained.tables.len());
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::ciphertexts::Decision;
+use crate::common::error::{Error, Result};
+
+// `prev_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// Request an entry was recorded for. Identification and ranking evaluate the probe against every
+// enrolled subject and are recorded with one entry per subject, the client reports no decision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Request {
+    #[default]
+    Verify,
+    Identify,
+    Rank,
+}
+
+// One verification attempt. Entries are stored as one JSON object per line, `prev_hash` is the
+// SHA-256 over the previous line, so modifying, removing or reordering lines breaks the chain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    // Missing in entries written before identification and ranking were recorded
+    #[serde(default)]
+    pub request: Request,
+    pub subject_id: String,
+    pub dataset: String,
+    pub num_tables: usize,
+    pub decomp_base: u64,
+    pub params: MultiBitPBSParameters,
+    // SHA-256 over the encoded encrypted probe and the encoded encrypted decision
+    pub probe_hash: String,
+    pub response_hash: String,
+    // Decision the client reported after decrypting, None if it did not report one
+    pub client_decision: Option<Decision>,
+    pub prev_hash: String,
+}
+
+// Everything of an entry except its position in the chain
+pub struct Attempt {
+    pub request: Request,
+    pub subject_id: String,
+    pub dataset: String,
+    pub num_tables: usize,
+    pub decomp_base: u64,
+    pub params: MultiBitPBSParameters,
+    pub probe_hash: String,
+    pub response_hash: String,
+}
+
+// Number of entries and hash of the last one, kept next to the log in `<log>.head`. Truncating the
+// log keeps the remaining chain intact, the head is what exposes it.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditHead {
+    pub entries: u64,
+    pub hash: String,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn head_path(path: &str) -> String {
+    format!("{}.head", path)
+}
+
+fn audit_error(path: &str, reason: String) -> Error {
+    Error::Audit { path: path.to_string(), reason }
+}
+
+// Append-only audit log. Every append is synced to disk before the head is replaced.
+pub struct AuditLog {
+    path: String,
+    file: File,
+    head: AuditHead,
+}
+
+impl AuditLog {
+    // Opens the log at `path`, creating it if needed. An existing log is verified first, nothing
+    // is appended to a log that was tampered with. A head recording entries without a log means
+    // the log was deleted, it is not started over.
+    pub fn open(path: &str) -> Result<Self> {
+        let head = if fs::metadata(path).is_ok() {
+            verify_log(path)?
+        } else {
+            let head = read_head(path)?;
+            if head.entries > 0 {
+                return Err(audit_error(path, format!("the head records {} entries but the log is missing", head.entries)));
+            }
+            head
+        };
+        // Catches up with an entry written right before a crash
+        write_head(path, &head)?;
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| Error::io(path, err))?;
+        Ok(AuditLog { path: path.to_string(), file, head })
+    }
+
+    pub fn append(&mut self, attempt: Attempt, client_decision: Option<Decision>) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        let entry = AuditEntry {
+            seq: self.head.entries,
+            timestamp,
+            request: attempt.request,
+            subject_id: attempt.subject_id,
+            dataset: attempt.dataset,
+            num_tables: attempt.num_tables,
+            decomp_base: attempt.decomp_base,
+            params: attempt.params,
+            probe_hash: attempt.probe_hash,
+            response_hash: attempt.response_hash,
+            client_decision,
+            prev_hash: self.head.hash.clone(),
+        };
+        let line = serde_json::to_string(&entry).map_err(|err| Error::format("audit entry", &err.to_string()))?;
+        writeln!(self.file, "{}", line).map_err(|err| Error::io(&self.path, err))?;
+        self.file.sync_all().map_err(|err| Error::io(&self.path, err))?;
+
+        self.head = AuditHead { entries: self.head.entries + 1, hash: sha256_hex(line.as_bytes()) };
+        write_head(&self.path, &self.head)
+    }
+}
+
+// Written to a temporary file and renamed, so a crash leaves either the old or the new head
+fn write_head(path: &str, head: &AuditHead) -> Result<()> {
+    let head_path = head_path(path);
+    let tmp_path = format!("{}.tmp", head_path);
+    let content = serde_json::to_string(head).map_err(|err| Error::format("audit head", &err.to_string()))?;
+    fs::write(&tmp_path, content).map_err(|err| Error::io(&tmp_path, err))?;
+    fs::rename(&tmp_path, &head_path).map_err(|err| Error::io(&head_path, err))
+}
+
+// A missing head is the genesis head of an empty log
+fn read_head(path: &str) -> Result<AuditHead> {
+    let head_path = head_path(path);
+    match fs::read_to_string(&head_path) {
+        Ok(head) => serde_json::from_str(&head).map_err(|err| Error::format(&head_path, &err.to_string())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(AuditHead { entries: 0, hash: GENESIS_HASH.to_string() }),
+        Err(err) => Err(Error::io(&head_path, err)),
+    }
+}
+
+// Checks the hash chain and the sequence numbers of every entry and that the chain passes through
+// the head. The log may be one entry ahead of the head after a crash between the two writes.
+// Returns the head of the verified chain.
+pub fn verify_log(path: &str) -> Result<AuditHead> {
+    let head = read_head(path)?;
+    let content = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    if !content.is_empty() && !content.ends_with('\n') {
+        return Err(audit_error(path, "the last entry is incomplete".to_string()));
+    }
+
+    let mut computed = AuditHead { entries: 0, hash: GENESIS_HASH.to_string() };
+    let mut passes_head = head == computed;
+    for (idx, line) in content.lines().enumerate() {
+        let entry: AuditEntry = serde_json::from_str(line)
+            .map_err(|err| audit_error(path, format!("entry {} can not be parsed: {}", idx, err)))?;
+        if entry.seq != computed.entries {
+            return Err(audit_error(path, format!("entry {} has sequence number {}", idx, entry.seq)));
+        }
+        if entry.prev_hash != computed.hash {
+            return Err(audit_error(path, format!("entry {} does not chain to the previous entry", idx)));
+        }
+        computed = AuditHead { entries: computed.entries + 1, hash: sha256_hex(line.as_bytes()) };
+        passes_head |= head == computed;
+    }
+
+    if head.entries > computed.entries {
+        return Err(audit_error(path, format!("truncated: the head records {} entries, the log holds {}", head.entries, computed.entries)));
+    }
+    if !passes_head {
+        return Err(audit_error(path, format!("entry {} does not match the hash recorded in the head", head.entries.saturating_sub(1))));
+    }
+    if computed.entries > head.entries + 1 {
+        return Err(audit_error(path, format!("the head records {} entries, the log holds {}", head.entries, computed.entries)));
+    }
+    Ok(computed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tfhe_utils::get_params_multi_bit_gpu;
+
+    // Fresh log with `entries` appended entries, in the temporary directory
+    fn written_log(name: &str, entries: usize) -> String {
+        let path = std::env::temp_dir().join(format!("audit_{}_{}.log", name, std::process::id())).to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(head_path(&path));
+        let mut log = AuditLog::open(&path).unwrap();
+        for idx in 0..entries {
+            let attempt = Attempt {
+                request: Request::Verify,
+                subject_id: format!("subject{}", idx),
+                dataset: "PUT".to_string(),
+                num_tables: 49,
+                decomp_base: 8,
+                params: get_params_multi_bit_gpu(8).unwrap(),
+                probe_hash: sha256_hex(b"probe"),
+                response_hash: sha256_hex(b"response"),
+            };
+            log.append(attempt, Some(Decision::Reject)).unwrap();
+        }
+        path
+    }
+
+    fn rewrite_lines(path: &str, edit: impl FnOnce(&mut Vec<String>)) {
+        let mut lines = fs::read_to_string(path).unwrap().lines().map(str::to_string).collect::<Vec<_>>();
+        edit(&mut lines);
+        fs::write(path, lines.iter().map(|line| format!("{}\n", line)).collect::<String>()).unwrap();
+    }
+
+    #[test]
+    fn intact_log_verifies_and_reopens() {
+        let path = written_log("intact", 3);
+        assert_eq!(verify_log(&path).unwrap().entries, 3);
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.head.entries, 3);
+        let attempt = Attempt {
+            request: Request::Identify,
+            subject_id: "subject3".to_string(),
+            dataset: "PUT".to_string(),
+            num_tables: 49,
+            decomp_base: 8,
+            params: get_params_multi_bit_gpu(8).unwrap(),
+            probe_hash: sha256_hex(b"probe"),
+            response_hash: sha256_hex(b"response"),
+        };
+        log.append(attempt, None).unwrap();
+        assert_eq!(verify_log(&path).unwrap().entries, 4);
+    }
+
+    #[test]
+    fn detects_modified_entry() {
+        let path = written_log("modified", 3);
+        rewrite_lines(&path, |lines| lines[1] = lines[1].replace("subject1", "subject9"));
+        assert!(matches!(verify_log(&path), Err(Error::Audit { .. })));
+    }
+
+    #[test]
+    fn detects_modified_last_entry() {
+        let path = written_log("modified_last", 3);
+        rewrite_lines(&path, |lines| lines[2] = lines[2].replace("Reject", "Accept"));
+        assert!(matches!(verify_log(&path), Err(Error::Audit { .. })));
+    }
+
+    #[test]
+    fn detects_truncated_log() {
+        let path = written_log("truncated", 3);
+        rewrite_lines(&path, |lines| lines.truncate(2));
+        assert!(matches!(verify_log(&path), Err(Error::Audit { .. })));
+    }
+
+    #[test]
+    fn detects_reordered_entries() {
+        let path = written_log("reordered", 3);
+        rewrite_lines(&path, |lines| lines.swap(1, 2));
+        assert!(matches!(verify_log(&path), Err(Error::Audit { .. })));
+    }
+
+    #[test]
+    fn refuses_to_open_without_log() {
+        let path = written_log("deleted", 2);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(AuditLog::open(&path), Err(Error::Audit { .. })));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::common::backend::{Backend, ComparisonType};
+use crate::common::ciphertexts::Decision;
+use crate::common::config::Config;
+use crate::common::data::get_data;
+use crate::common::diagnostics::{radix_value, DiagnosticTrace};
+use crate::common::error::{Error, Result};
+use crate::common::keygen::genkeys_multibit;
+use crate::common::reference::run_reference;
+use crate::common::util::luts_to_closures;
+use itertools::Itertools;
+use crate::common::tfhe_utils::short_params;
+use std::time::Instant;
+
+// Runs the pipeline stage by stage on the sample at index 0. Only the decision is decrypted, unless
+// `trace_path` is given: then the PBS output and the sum are decrypted too and written to a
+// diagnostic trace at `trace_path`, together with the plaintext reference.
+pub fn auth<B: Backend>(config: Config, backend: &B, trace_path: Option<&str>) -> Result<()> {
+    let ((reject_threshold, accept_threshold), repeated_probes, luts, output_indices, sum_block_len, params) = get_data(&config, 0)?;
+    let short_params = short_params(params);
+
+    let decomp_base = params.message_modulus.0 as u64;
+    let reference = run_reference(&luts, &repeated_probes, &output_indices, sum_block_len, decomp_base, (reject_threshold, accept_threshold))?;
+    println!("Expected: {}", reference.decision);
+
+    let num_cts = luts.len();
+    let pbs_out_blocks = num_cts * sum_block_len;
+    let num_total_blocks = output_indices.len();
+    let flat_luts = luts.into_iter().flatten().collect_vec();
+
+    println!("Backend: {}", backend.name());
+    println!("Key gen...");
+    let (mut client_key, server_key) = genkeys_multibit(params);
+    let evaluation_key = backend.evaluation_key(&server_key);
+
+    println!("Encrypt...");
+    let lwe_ciphertext_in = backend.lwe_to_backend(&client_key.encrypt_list(&repeated_probes));
+
+    let functions = luts_to_closures(flat_luts);
+    let glwe_luts = backend.glwe_to_backend(&client_key.encrypt_luts(&functions));
+    let lwe_size = params.glwe_dimension.to_equivalent_lwe_dimension(params.polynomial_size).to_lwe_size();
+
+    let start = Instant::now();
+    //println!("PBS...");
+    let mut pbs_res = backend.encrypted_pbs(
+        lwe_ciphertext_in,
+        glwe_luts,
+        output_indices,
+        num_total_blocks,
+        pbs_out_blocks,
+        lwe_size,
+        &short_params,
+        &evaluation_key,
+    );
+    let pbs_elapsed = start.elapsed();
+    // The sum works in place on the PBS output, keep the blocks for the trace
+    let clear_pbs = trace_path.map(|_| client_key.decrypt_list(backend.lwe_to_host(&pbs_res).as_view()));
+
+    let start = Instant::now();
+    //println!("Sum...");
+    let sum_res = backend.sum(
+        &mut pbs_res,
+        sum_block_len,
+        num_cts,
+        &evaluation_key,
+        params,
+    )?;
+
+    //println!("Comparison...");
+    let reject_res = backend.comparison(
+        &sum_res,
+        reject_threshold,
+        &evaluation_key,
+        ComparisonType::GE,
+        params,
+    );
+    let mut comp_res = backend.comparison(
+        &sum_res,
+        accept_threshold,
+        &evaluation_key,
+        ComparisonType::GE,
+        params,
+    );
+    backend.add_assign(&mut comp_res, &reject_res);
+    let elapsed = pbs_elapsed + start.elapsed();
+
+    println!("Decrypting...");
+    let result = client_key.decrypt_list(backend.lwe_to_host(&comp_res).as_view());
+    let decision = Decision::from_count(*result.first().ok_or_else(|| Error::shape("decision blocks", 1, 0))?)?;
+    println!("Got: {}", decision);
+    if decision != reference.decision {
+        println!("Decision differs from the plaintext reference");
+    }
+    println!("Total time needed: {}s", elapsed.as_secs_f64());
+
+    if let (Some(path), Some(clear_pbs)) = (trace_path, clear_pbs) {
+        let clear_sum = client_key.decrypt_list(backend.lwe_to_host(&sum_res).as_view());
+        let trace = DiagnosticTrace {
+            dataset: config.data_set_name.to_string(),
+            backend: backend.name().to_string(),
+            first_mismatch: reference.first_mismatch(&clear_pbs, &clear_sum, &result),
+            comparison_input: radix_value(&clear_sum, decomp_base),
+            pbs_outputs: clear_pbs,
+            sum_blocks: clear_sum,
+            reject_threshold,
+            accept_threshold,
+            decision,
+            reference,
+            pbs_seconds: pbs_elapsed.as_secs_f64(),
+            total_seconds: elapsed.as_secs_f64(),
+        };
+        trace.save(path)?;
+        match &trace.first_mismatch {
+            Some(mismatch) => println!("Mismatch with the plaintext reference: {}, trace written to {}", mismatch, path),
+            None => println!("Matches the plaintext reference, trace written to {}", path),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+use tfhe::core_crypto::prelude::*;
+use tfhe::shortint::{MultiBitPBSParameters, ShortintParameterSet};
+use crate::common::error;
+use crate::common::keys::ServerKey;
+use crate::common::tfhe_utils::split_lwe_list;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonType {
+    GT,
+    GE,
+    LT,
+    LE,
+    EQ,
+    NE,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    Gpu,
+    Cpu,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gpu" | "cuda" => Ok(BackendKind::Gpu),
+            "cpu" | "host" => Ok(BackendKind::Cpu),
+            _ => Err(format!("Unknown backend '{}', expected 'gpu' or 'cpu'", s)),
+        }
+    }
+}
+
+// Execution target of the server side stages. It only ever sees the public ServerKey, encryption
+// and decryption happen on the client with the ClientKey.
+pub trait Backend {
+    type LweList;
+    type GlweList;
+    type EvaluationKey;
+
+    fn name(&self) -> &'static str;
+
+    fn evaluation_key(&self, server_key: &ServerKey) -> Self::EvaluationKey;
+
+    fn lwe_to_backend(&self, list: &LweCiphertextListOwned<u64>) -> Self::LweList;
+
+    fn glwe_to_backend(&self, list: &GlweCiphertextListOwned<u64>) -> Self::GlweList;
+
+    fn lwe_to_host(&self, list: &Self::LweList) -> LweCiphertextListOwned<u64>;
+
+    // Splits a list into consecutive lists of `chunk_len` ciphertexts, going through the host
+    fn split_lwe(&self, list: &Self::LweList, chunk_len: usize) -> Vec<Self::LweList> {
+        split_lwe_list(&self.lwe_to_host(list), chunk_len)
+            .iter()
+            .map(|chunk| self.lwe_to_backend(chunk))
+            .collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn encrypted_pbs(
+        &self,
+        cts_in: Self::LweList,
+        luts: Self::GlweList,
+        output_indices: Vec<u64>,
+        num_total_blocks: usize,
+        num_output_blocks: usize,
+        lwe_size: LweSize,
+        short_params: &ShortintParameterSet,
+        key: &Self::EvaluationKey,
+    ) -> Self::LweList;
+
+    fn sum(&self, input: &mut Self::LweList, blocks: usize, num_cts: usize, key: &Self::EvaluationKey, params: MultiBitPBSParameters) -> error::Result<Self::LweList>;
+
+    fn comparison(&self, input: &Self::LweList, scalar: u64, key: &Self::EvaluationKey, op: ComparisonType, params: MultiBitPBSParameters) -> Self::LweList;
+
+    // Adds `rhs` to `lhs` block wise, without any bootstrapping
+    fn add_assign(&self, lhs: &mut Self::LweList, rhs: &Self::LweList);
+
+    // Compares two radix ciphertexts of the same length, e.g. the score and an encrypted threshold
+    fn comparison_ct(&self, lhs: &Self::LweList, rhs: &Self::LweList, key: &Self::EvaluationKey, op: ComparisonType, params: MultiBitPBSParameters) -> Self::LweList;
+
+    // Encrypted ranking of the per subject sums: index and decision of the `k` best subjects
+    fn top_k(&self, scores: &[Self::LweList], decisions: &[Self::LweList], k: usize, key: &Self::EvaluationKey, params: MultiBitPBSParameters) -> error::Result<Vec<(Self::LweList, Self::LweList)>>;
+}
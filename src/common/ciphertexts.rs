@@ -0,0 +1,359 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use tfhe::core_crypto::prelude::*;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::{Error, Result};
+use crate::common::serialization::{self, FileKind};
+use crate::common::tfhe_utils::get_params_multi_bit_gpu;
+
+// Template versions 2: encrypted threshold, 3: reject and accept thresholds
+const TEMPLATE_FILE: FileKind = FileKind { magic: *b"SBET", version: 3 };
+const PROBE_FILE: FileKind = FileKind { magic: *b"SBEP", version: 1 };
+// Decision version 2: number of thresholds reached instead of a match bit
+const DECISION_FILE: FileKind = FileKind { magic: *b"SBED", version: 2 };
+
+// Encrypted HELR lookup tables of one enrolled template, together with their layout. LUT `i`
+// evaluates one base `decomp_base` digit of a table row and its PBS output is written to block
+// `lut_output_indices[i]` of the radix sum, table `t` providing `decomp_lengths[t]` LUTs. The
+// offset reject and accept thresholds of the subject are encrypted as radix numbers of
+// `sum_block_len` blocks.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedTemplate {
+    pub dataset: String,
+    pub decomp_base: u64,
+    pub decomp_lengths: Vec<usize>,
+    pub lut_output_indices: Vec<u64>,
+    pub sum_block_len: usize,
+    pub reject_threshold: LweCiphertextListOwned<u64>,
+    pub accept_threshold: LweCiphertextListOwned<u64>,
+    pub luts: GlweCiphertextListOwned<u64>,
+}
+
+// Encrypted quantized probe, feature `t` repeated `decomp_lengths[t]` times
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedProbe {
+    pub dataset: String,
+    pub decomp_base: u64,
+    pub decomp_lengths: Vec<usize>,
+    pub cts: LweCiphertextListOwned<u64>,
+}
+
+// Encrypted outcome of the threshold comparisons, a single block holding the number of
+// thresholds the score reached (see Decision)
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedDecision {
+    pub decomp_base: u64,
+    pub ct: LweCiphertextListOwned<u64>,
+}
+
+// Encrypted radix index of a subject selected by the ranking, with its threshold decision
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedCandidate {
+    pub index: LweCiphertextListOwned<u64>,
+    pub decision: EncryptedDecision,
+}
+
+// Outcome of an identification: one encrypted decision per template, and the encrypted index and
+// decision of the best matching template, the only part the client decrypts
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedIdentification {
+    pub decisions: Vec<EncryptedDecision>,
+    pub best: EncryptedCandidate,
+}
+
+// Outcome of an authentication attempt
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Decision {
+    Reject,
+    // The score lies between the two thresholds, a second factor is required
+    StepUp,
+    Accept,
+}
+
+impl Decision {
+    // Decodes the number of thresholds reached by the score. Any other value, e.g. from a
+    // ciphertext decrypted with the wrong key or too noisy, is an error and never an accept.
+    pub fn from_count(count: u64) -> Result<Self> {
+        match count {
+            0 => Ok(Decision::Reject),
+            1 => Ok(Decision::StepUp),
+            2 => Ok(Decision::Accept),
+            _ => Err(Error::format("decision", &format!("decrypted value {} is not a number of thresholds", count))),
+        }
+    }
+
+    pub fn from_score(score: u64, reject_threshold: u64, accept_threshold: u64) -> Self {
+        if score >= accept_threshold && score >= reject_threshold {
+            Decision::Accept
+        } else if score >= reject_threshold {
+            Decision::StepUp
+        } else {
+            Decision::Reject
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Reject => write!(f, "reject"),
+            Decision::StepUp => write!(f, "step-up"),
+            Decision::Accept => write!(f, "accept"),
+        }
+    }
+}
+
+fn check_lwe_list(context: &str, list: &LweCiphertextListOwned<u64>, lwe_size: LweSize, params: MultiBitPBSParameters) -> Result<()> {
+    if list.lwe_size() != lwe_size {
+        return Err(Error::shape(&format!("{} lwe size", context), lwe_size.0, list.lwe_size().0));
+    }
+    if list.as_ref().len() % lwe_size.0 != 0 {
+        return Err(Error::format(context, "the ciphertext data is not a multiple of the lwe size"));
+    }
+    if list.ciphertext_modulus() != params.ciphertext_modulus {
+        return Err(Error::format(context, "the ciphertext modulus differs from the parameters"));
+    }
+    Ok(())
+}
+
+fn check_glwe_list(context: &str, list: &GlweCiphertextListOwned<u64>, params: MultiBitPBSParameters) -> Result<()> {
+    let glwe_size = params.glwe_dimension.to_glwe_size();
+    if list.glwe_size() != glwe_size {
+        return Err(Error::shape(&format!("{} glwe size", context), glwe_size.0, list.glwe_size().0));
+    }
+    if list.polynomial_size() != params.polynomial_size {
+        return Err(Error::shape(&format!("{} polynomial size", context), params.polynomial_size.0, list.polynomial_size().0));
+    }
+    if list.as_ref().len() % (glwe_size.0 * params.polynomial_size.0) != 0 {
+        return Err(Error::format(context, "the ciphertext data is not a multiple of the glwe ciphertext size"));
+    }
+    if list.ciphertext_modulus() != params.ciphertext_modulus {
+        return Err(Error::format(context, "the ciphertext modulus differs from the parameters"));
+    }
+    Ok(())
+}
+
+impl EncryptedTemplate {
+    pub fn params(&self) -> Result<MultiBitPBSParameters> {
+        get_params_multi_bit_gpu(self.decomp_base)
+    }
+
+    pub fn num_tables(&self) -> usize {
+        self.decomp_lengths.len()
+    }
+
+    // Checks the ciphertext sizes and moduli against the parameters of the decomposition base and
+    // the layout against the ciphertext counts, the PBS asserts on both
+    pub fn validate(&self) -> Result<()> {
+        let params = self.params()?;
+        check_glwe_list("template luts", &self.luts, params)?;
+        let radix_lwe_size = params.glwe_dimension.to_equivalent_lwe_dimension(params.polynomial_size).to_lwe_size();
+        check_lwe_list("template reject threshold", &self.reject_threshold, radix_lwe_size, params)?;
+        check_lwe_list("template accept threshold", &self.accept_threshold, radix_lwe_size, params)?;
+
+        let total_luts: usize = self.decomp_lengths.iter().sum();
+        if self.lut_output_indices.len() != total_luts {
+            return Err(Error::shape("template lut output indices", total_luts, self.lut_output_indices.len()));
+        }
+        if self.luts.glwe_ciphertext_count().0 != total_luts {
+            return Err(Error::shape("template luts", total_luts, self.luts.glwe_ciphertext_count().0));
+        }
+        let num_output_blocks = self.num_tables() * self.sum_block_len;
+        if let Some(idx) = self.lut_output_indices.iter().find(|idx| **idx as usize >= num_output_blocks) {
+            return Err(Error::shape("template sum blocks", num_output_blocks, *idx as usize + 1));
+        }
+        for threshold in [&self.reject_threshold, &self.accept_threshold] {
+            if threshold.lwe_ciphertext_count().0 != self.sum_block_len {
+                return Err(Error::shape("template threshold blocks", self.sum_block_len, threshold.lwe_ciphertext_count().0));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serialization::to_bytes(TEMPLATE_FILE, self.params()?, self)
+    }
+
+    pub fn from_bytes(source: &str, bytes: &[u8]) -> Result<Self> {
+        let (params, template): (_, Self) = serialization::from_bytes(source, TEMPLATE_FILE, None, bytes)?;
+        template.check_params(source, params)?;
+        Ok(template)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        serialization::save(path, TEMPLATE_FILE, self.params()?, self)
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let (params, template): (_, Self) = serialization::load(path, TEMPLATE_FILE, None)?;
+        template.check_params(path, params)?;
+        Ok(template)
+    }
+
+    fn check_params(&self, source: &str, params: MultiBitPBSParameters) -> Result<()> {
+        if self.params()? != params {
+            return Err(Error::ParameterMismatch(source.to_string()));
+        }
+        self.validate()
+    }
+}
+
+impl EncryptedProbe {
+    pub fn params(&self) -> Result<MultiBitPBSParameters> {
+        get_params_multi_bit_gpu(self.decomp_base)
+    }
+
+    // Checks the ciphertext size and modulus against the parameters and the block count against
+    // the layout, a malformed probe must not reach the PBS
+    pub fn validate(&self) -> Result<()> {
+        let params = self.params()?;
+        check_lwe_list("probe blocks", &self.cts, params.lwe_dimension.to_lwe_size(), params)?;
+        let total_blocks: usize = self.decomp_lengths.iter().sum();
+        if self.cts.lwe_ciphertext_count().0 != total_blocks {
+            return Err(Error::shape("probe blocks", total_blocks, self.cts.lwe_ciphertext_count().0));
+        }
+        Ok(())
+    }
+
+    // Checks that the probe was encrypted with the layout of `template`
+    pub fn check_compatible(&self, template: &EncryptedTemplate) -> Result<()> {
+        if !self.dataset.eq_ignore_ascii_case(&template.dataset) || self.decomp_base != template.decomp_base {
+            return Err(Error::format("probe", &format!("probe for {} (base {}) does not match template for {} (base {})", self.dataset, self.decomp_base, template.dataset, template.decomp_base)));
+        }
+        if self.decomp_lengths != template.decomp_lengths {
+            return Err(Error::format("probe", "decomposition lengths differ from the template"));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serialization::to_bytes(PROBE_FILE, self.params()?, self)
+    }
+
+    pub fn from_bytes(source: &str, bytes: &[u8]) -> Result<Self> {
+        let (params, probe): (_, Self) = serialization::from_bytes(source, PROBE_FILE, None, bytes)?;
+        probe.check_params(source, params)?;
+        Ok(probe)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        serialization::save(path, PROBE_FILE, self.params()?, self)
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let (params, probe): (_, Self) = serialization::load(path, PROBE_FILE, None)?;
+        probe.check_params(path, params)?;
+        Ok(probe)
+    }
+
+    fn check_params(&self, source: &str, params: MultiBitPBSParameters) -> Result<()> {
+        if self.params()? != params {
+            return Err(Error::ParameterMismatch(source.to_string()));
+        }
+        self.validate()
+    }
+}
+
+impl EncryptedDecision {
+    pub fn params(&self) -> Result<MultiBitPBSParameters> {
+        get_params_multi_bit_gpu(self.decomp_base)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serialization::to_bytes(DECISION_FILE, self.params()?, self)
+    }
+
+    pub fn from_bytes(source: &str, bytes: &[u8]) -> Result<Self> {
+        let (params, decision): (_, Self) = serialization::from_bytes(source, DECISION_FILE, None, bytes)?;
+        if decision.params()? != params {
+            return Err(Error::ParameterMismatch(source.to_string()));
+        }
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn probe(lwe_size: LweSize, ciphertext_modulus: CiphertextModulus<u64>) -> EncryptedProbe {
+        EncryptedProbe {
+            dataset: "PUT".to_string(),
+            decomp_base: 8,
+            decomp_lengths: vec![2, 1],
+            cts: LweCiphertextList::new(0, lwe_size, LweCiphertextCount(3), ciphertext_modulus),
+        }
+    }
+
+    pub(crate) fn template(glwe_size: GlweSize, polynomial_size: PolynomialSize) -> EncryptedTemplate {
+        let params = get_params_multi_bit_gpu(8).unwrap();
+        let radix_lwe_size = params.glwe_dimension.to_equivalent_lwe_dimension(params.polynomial_size).to_lwe_size();
+        let threshold = LweCiphertextList::new(0, radix_lwe_size, LweCiphertextCount(2), params.ciphertext_modulus);
+        EncryptedTemplate {
+            dataset: "PUT".to_string(),
+            decomp_base: 8,
+            decomp_lengths: vec![2, 1],
+            lut_output_indices: vec![0, 1, 2],
+            sum_block_len: 2,
+            reject_threshold: threshold.clone(),
+            accept_threshold: threshold,
+            luts: GlweCiphertextList::new(0, glwe_size, polynomial_size, GlweCiphertextCount(3), params.ciphertext_modulus),
+        }
+    }
+
+    #[test]
+    fn decodes_only_defined_decision_counts() {
+        assert_eq!(Decision::from_count(0).unwrap(), Decision::Reject);
+        assert_eq!(Decision::from_count(1).unwrap(), Decision::StepUp);
+        assert_eq!(Decision::from_count(2).unwrap(), Decision::Accept);
+        for count in [3, 7, 63, u64::MAX] {
+            assert!(matches!(Decision::from_count(count), Err(Error::Format { .. })));
+        }
+    }
+
+    #[test]
+    fn decision_from_score() {
+        assert_eq!(Decision::from_score(9, 10, 20), Decision::Reject);
+        assert_eq!(Decision::from_score(10, 10, 20), Decision::StepUp);
+        assert_eq!(Decision::from_score(20, 10, 20), Decision::Accept);
+        assert_eq!(Decision::from_score(15, 15, 15), Decision::Accept);
+    }
+
+    #[test]
+    fn probe_validation_checks_ciphertexts() {
+        let params = get_params_multi_bit_gpu(8).unwrap();
+        let lwe_size = params.lwe_dimension.to_lwe_size();
+        assert!(probe(lwe_size, params.ciphertext_modulus).validate().is_ok());
+        assert!(matches!(probe(LweSize(lwe_size.0 + 1), params.ciphertext_modulus).validate(), Err(Error::Shape { .. })));
+        let other_modulus = CiphertextModulus::try_new_power_of_2(32).unwrap();
+        assert!(matches!(probe(lwe_size, other_modulus).validate(), Err(Error::Format { .. })));
+
+        let mut short_probe = probe(lwe_size, params.ciphertext_modulus);
+        short_probe.decomp_lengths = vec![2, 2];
+        assert!(matches!(short_probe.validate(), Err(Error::Shape { .. })));
+    }
+
+    #[test]
+    fn compatibility_ignores_the_case_of_the_dataset() {
+        let params = get_params_multi_bit_gpu(8).unwrap();
+        let template = template(params.glwe_dimension.to_glwe_size(), params.polynomial_size);
+        let mut probe = probe(params.lwe_dimension.to_lwe_size(), params.ciphertext_modulus);
+        probe.dataset = "put".to_string();
+        assert!(probe.check_compatible(&template).is_ok());
+        probe.dataset = "FRGC".to_string();
+        assert!(matches!(probe.check_compatible(&template), Err(Error::Format { .. })));
+    }
+
+    #[test]
+    fn template_validation_checks_ciphertexts() {
+        let params = get_params_multi_bit_gpu(8).unwrap();
+        let glwe_size = params.glwe_dimension.to_glwe_size();
+        assert!(template(glwe_size, params.polynomial_size).validate().is_ok());
+        assert!(matches!(template(GlweSize(glwe_size.0 + 1), params.polynomial_size).validate(), Err(Error::Shape { .. })));
+        assert!(matches!(template(glwe_size, PolynomialSize(params.polynomial_size.0 / 2)).validate(), Err(Error::Shape { .. })));
+
+        let mut bad_threshold = template(glwe_size, params.polynomial_size);
+        bad_threshold.accept_threshold = LweCiphertextList::new(0, params.lwe_dimension.to_lwe_size(), LweCiphertextCount(2), params.ciphertext_modulus);
+        assert!(matches!(bad_threshold.validate(), Err(Error::Shape { .. })));
+    }
+}
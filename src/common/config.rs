@@ -1,34 +1,212 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::common::error::{Error, Result};
+
+// Manifest read when no other one is given
+pub const DEFAULT_MANIFEST: &str = "data/datasets.toml";
+
+const DEFAULT_TABLE_PREFIX: &str = "HELR";
+const QBIN_SUFFIX: &str = "_qbins";
+
+#[derive(Clone, Debug)]
 pub struct Config {
-    pub data_set_name: &'static str,
+    pub data_set_name: String,
+    // CSV with one feature vector per line, the column `label_column` holds the subject label
+    pub feature_vector_path: String,
+    pub label_column: usize,
+    // The HELR tables are `<tables_dir>/<table_prefix><idx>.csv`
+    pub tables_dir: String,
+    pub table_prefix: String,
+    pub qbins_path: String,
     pub num_tables: usize,
-    pub threshold: i64,
-}
-
-pub const BMDB1: Config = Config {
-    data_set_name: "BMDB",
-    num_tables: 36,
-    threshold: 14,
-};
-pub const _BMDB2: Config = Config {
-    data_set_name: "BMDB",
-    num_tables: 36,
-    threshold: 14,
-};
-pub const PUT: Config = Config {
-    data_set_name: "PUT",
-    num_tables: 49,
-    threshold: -53,
-};
-
-pub const FRGC: Config = Config {
-    data_set_name: "FRGC",
-    num_tables: 94,
-    threshold: -1,
-};
-
-//constant values
-pub const TABLE_PREFIX: &str = "HELR";
-pub const QBIN_SUFFIX: &str = "_qbins";
-pub const PATH_SEPARATOR: &str = "/";
-pub const DATA_PATH: &str = "data";
-pub const LOOKUP_TABLES_FOLDER: &str = "lookupTables";
\ No newline at end of file
+    // Scores below reject_threshold are rejected, scores from accept_threshold on are accepted and
+    // scores in between ask for a second factor
+    pub reject_threshold: i64,
+    pub accept_threshold: i64,
+    pub params: ParamOverrides,
+}
+
+// Replaces values otherwise derived from the HELR tables
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamOverrides {
+    // Must be at least the smallest base covering the table dimension
+    pub decomp_base: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(rename = "dataset", default)]
+    datasets: Vec<DatasetEntry>,
+}
+
+// One `[[dataset]]` table of the manifest. Paths are relative to the directory of the manifest,
+// `threshold` sets both thresholds at once.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DatasetEntry {
+    name: String,
+    features: String,
+    label_column: Option<usize>,
+    tables: String,
+    table_prefix: Option<String>,
+    qbins: Option<String>,
+    num_tables: usize,
+    threshold: Option<i64>,
+    reject_threshold: Option<i64>,
+    accept_threshold: Option<i64>,
+    #[serde(default)]
+    params: ParamOverrides,
+}
+
+impl DatasetEntry {
+    fn into_config(self, manifest_path: &str, base_dir: &Path) -> Result<Config> {
+        let (reject_threshold, accept_threshold) = match (self.threshold, self.reject_threshold, self.accept_threshold) {
+            (Some(threshold), None, None) => (threshold, threshold),
+            (None, Some(reject), Some(accept)) => (reject, accept),
+            _ => return Err(Error::format(manifest_path, &format!("dataset {} needs either threshold or both reject_threshold and accept_threshold", self.name))),
+        };
+        if reject_threshold > accept_threshold {
+            return Err(Error::format(manifest_path, &format!("dataset {}: reject_threshold {} is above accept_threshold {}", self.name, reject_threshold, accept_threshold)));
+        }
+        let resolve = |path: &str| base_dir.join(path).to_string_lossy().to_string();
+        let tables_dir = resolve(&self.tables);
+        let qbins_path = match &self.qbins {
+            Some(qbins) => resolve(qbins),
+            None => Path::new(&tables_dir).join(format!("{}{}.csv", self.name, QBIN_SUFFIX)).to_string_lossy().to_string(),
+        };
+        Ok(Config {
+            feature_vector_path: resolve(&self.features),
+            label_column: self.label_column.unwrap_or(0),
+            tables_dir,
+            table_prefix: self.table_prefix.unwrap_or_else(|| DEFAULT_TABLE_PREFIX.to_string()),
+            qbins_path,
+            num_tables: self.num_tables,
+            reject_threshold,
+            accept_threshold,
+            params: self.params,
+            data_set_name: self.name,
+        })
+    }
+}
+
+impl Config {
+    pub fn table_path(&self, idx: usize) -> String {
+        Path::new(&self.tables_dir).join(format!("{}{}.csv", self.table_prefix, idx)).to_string_lossy().to_string()
+    }
+}
+
+// All datasets declared in the TOML manifest at `path`, in file order
+pub fn load_manifest(path: &str) -> Result<Vec<Config>> {
+    let content = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    let manifest: Manifest = toml::from_str(&content).map_err(|err| Error::format(path, &err.to_string()))?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let configs = manifest.datasets.into_iter()
+        .map(|entry| entry.into_config(path, base_dir))
+        .collect::<Result<Vec<_>>>()?;
+    for (idx, config) in configs.iter().enumerate() {
+        if configs[..idx].iter().any(|other| other.data_set_name.eq_ignore_ascii_case(&config.data_set_name)) {
+            return Err(Error::format(path, &format!("dataset {} is declared twice", config.data_set_name)));
+        }
+    }
+    Ok(configs)
+}
+
+pub fn config_by_name(manifest_path: &str, name: &str) -> Result<Config> {
+    load_manifest(manifest_path)?
+        .into_iter()
+        .find(|config| config.data_set_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| Error::UnknownDataset(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `content` as the manifest of a fresh directory and returns the manifest path
+    fn manifest(name: &str, content: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("config_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("datasets.toml");
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn format_reason<T>(result: Result<T>) -> String {
+        match result {
+            Err(Error::Format { reason, .. }) => reason,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("manifest was accepted"),
+        }
+    }
+
+    const ENTRY: &str = "[[dataset]]\nname = \"PUT\"\nfeatures = \"PUT.csv\"\ntables = \"lookupTables/PUT\"\nnum_tables = 49\n";
+
+    #[test]
+    fn resolves_paths_against_the_manifest() {
+        let path = manifest("relative", &format!("{}threshold = -53\n", ENTRY));
+        let dir = Path::new(&path).parent().unwrap();
+        let config = config_by_name(&path, "PUT").unwrap();
+        assert_eq!(Path::new(&config.feature_vector_path), dir.join("PUT.csv"));
+        assert_eq!(Path::new(&config.tables_dir), dir.join("lookupTables/PUT"));
+        assert_eq!(Path::new(&config.qbins_path), dir.join("lookupTables/PUT/PUT_qbins.csv"));
+        assert_eq!(Path::new(&config.table_path(3)), dir.join("lookupTables/PUT/HELR3.csv"));
+        assert_eq!((config.reject_threshold, config.accept_threshold), (-53, -53));
+        assert_eq!(config.label_column, 0);
+        assert_eq!(config.params.decomp_base, None);
+    }
+
+    #[test]
+    fn keeps_absolute_paths_and_optional_entries() {
+        let path = manifest("absolute", "[[dataset]]\nname = \"NEW\"\nfeatures = \"/srv/new.csv\"\nlabel_column = 2\ntables = \"tables\"\n\
+            table_prefix = \"LLR\"\nqbins = \"bins/new.csv\"\nnum_tables = 4\nreject_threshold = -5\naccept_threshold = 10\n\n[dataset.params]\ndecomp_base = 8\n");
+        let dir = Path::new(&path).parent().unwrap();
+        let config = config_by_name(&path, "NEW").unwrap();
+        assert_eq!(config.feature_vector_path, "/srv/new.csv");
+        assert_eq!(Path::new(&config.qbins_path), dir.join("bins/new.csv"));
+        assert_eq!(Path::new(&config.table_path(0)), dir.join("tables/LLR0.csv"));
+        assert_eq!(config.label_column, 2);
+        assert_eq!((config.reject_threshold, config.accept_threshold), (-5, 10));
+        assert_eq!(config.params.decomp_base, Some(8));
+    }
+
+    #[test]
+    fn refuses_missing_and_unknown_entries() {
+        let no_threshold = manifest("no_threshold", ENTRY);
+        assert!(format_reason(load_manifest(&no_threshold)).contains("needs either threshold"));
+        let both = manifest("both", &format!("{}threshold = 1\nreject_threshold = 0\naccept_threshold = 2\n", ENTRY));
+        assert!(format_reason(load_manifest(&both)).contains("needs either threshold"));
+        let inverted = manifest("inverted", &format!("{}reject_threshold = 3\naccept_threshold = 2\n", ENTRY));
+        assert!(format_reason(load_manifest(&inverted)).contains("above accept_threshold"));
+        let no_tables = manifest("no_tables", "[[dataset]]\nname = \"PUT\"\nfeatures = \"PUT.csv\"\nnum_tables = 49\nthreshold = 0\n");
+        assert!(format_reason(load_manifest(&no_tables)).contains("tables"));
+        let unknown_field = manifest("unknown_field", &format!("{}threshold = 0\nthreshhold = 1\n", ENTRY));
+        assert!(format_reason(load_manifest(&unknown_field)).contains("threshhold"));
+        let twice = manifest("twice", &format!("{}threshold = 0\n\n{}threshold = 1\n", ENTRY, ENTRY.replace("PUT\"", "put\"")));
+        assert!(format_reason(load_manifest(&twice)).contains("declared twice"));
+    }
+
+    #[test]
+    fn looks_up_datasets_by_name() {
+        let path = manifest("lookup", &format!("{}threshold = 0\n", ENTRY));
+        assert_eq!(config_by_name(&path, "put").unwrap().data_set_name, "PUT");
+        assert!(matches!(config_by_name(&path, "FVC"), Err(Error::UnknownDataset(name)) if name == "FVC"));
+
+        let empty = manifest("empty", "# no datasets yet\n");
+        assert!(load_manifest(&empty).unwrap().is_empty());
+        assert!(matches!(config_by_name(&empty, "PUT"), Err(Error::UnknownDataset(_))));
+        let missing = Path::new(&empty).with_file_name("missing.toml").to_string_lossy().to_string();
+        assert!(matches!(load_manifest(&missing), Err(Error::Io { .. })));
+    }
+
+    #[test]
+    fn bundled_manifest_loads() {
+        let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), DEFAULT_MANIFEST);
+        let names = load_manifest(&path).unwrap().into_iter().map(|config| config.data_set_name).collect::<Vec<_>>();
+        assert_eq!(names, ["BMDB", "PUT", "FRGC"]);
+        assert!(Path::new(&config_by_name(&path, "PUT").unwrap().feature_vector_path).is_file());
+    }
+}
@@ -1,43 +1,71 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use itertools::{izip, Itertools};
+use std::str::FromStr;
+use itertools::{izip, repeat_n, Itertools};
 use rand::Rng;
 use tfhe::core_crypto::prelude::UnsignedInteger;
-use crate::common::config::{Config, DATA_PATH, LOOKUP_TABLES_FOLDER, PATH_SEPARATOR, QBIN_SUFFIX, TABLE_PREFIX};
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::config::Config;
+use crate::common::error::{Error, Result};
+use crate::common::tfhe_utils::get_params_multi_bit_gpu;
 use crate::common::util::{decompose_to_base, flip_vectors, length_in_base};
 
 
-pub fn load_and_offset_helr_tables(config: &Config) -> (i64, Vec<Vec<Vec<u64>>>) {
-    let helr_filename_basepath = [DATA_PATH, LOOKUP_TABLES_FOLDER, config.data_set_name, TABLE_PREFIX].join(PATH_SEPARATOR);
-    let helr_tables = read_helr_tables(helr_filename_basepath.as_str(), config.num_tables);
+pub fn load_and_offset_helr_tables(config: &Config) -> Result<(i64, Vec<Vec<Vec<u64>>>)> {
+    let helr_tables = read_helr_tables(config)?;
     offset_helr_tables(&helr_tables)
 }
 
-pub fn get_probe_and_template(config: &Config, index: usize) -> (Vec<u64>, Vec<u64>) {
-    let qbin_filename = format!("{}{}.csv", config.data_set_name, QBIN_SUFFIX);
-    let qbins_path = [DATA_PATH, LOOKUP_TABLES_FOLDER, config.data_set_name, qbin_filename.as_str()].join(PATH_SEPARATOR);
-    let qbins = read_qbins(qbins_path.as_str());
-
-    let feature_vector_filename = format!("{}.csv", config.data_set_name);
-    let feature_vector_path = [DATA_PATH, feature_vector_filename.as_str()].join(PATH_SEPARATOR);
-    let template = read_feature_vector(feature_vector_path.as_str(), index);
+pub fn get_probe_and_template(config: &Config, index: usize) -> Result<(Vec<u64>, Vec<u64>)> {
+    let qbins = read_qbins(&config.qbins_path)?;
+    let template = read_feature_vector(config, index)?;
     let probe = distort_feature_vector(&template);
 
-    let quantized_template = quantize_vector(&template, &qbins);
-    let quantized_probe = quantize_vector(&probe, &qbins);
+    let quantized_template = quantize_vector(&template, &qbins)?;
+    let quantized_probe = quantize_vector(&probe, &qbins)?;
+
+    Ok((quantized_probe, quantized_template))
+}
+
+// Smallest power of two base whose square covers the largest table dimension, the probe bin is
+// one message of the PBS and the message space is base * base. Tables are square, but their
+// dimensions may differ from feature to feature.
+pub fn get_min_decomp_base(tables: &[Vec<Vec<u64>>]) -> Result<u64> {
+    if tables.is_empty() {
+        return Err(Error::shape("HELR tables", 1, 0));
+    }
+    for table in tables {
+        if let Some(row) = table.iter().find(|row| row.len() != table.len()) {
+            return Err(Error::shape("HELR table columns", table.len(), row.len()));
+        }
+    }
+    let dimension = tables.iter().map(|table| table.len()).max().unwrap_or(0);
+    Ok(decomp_base_for_dimension(dimension))
+}
+
+// Smallest base with a parameter set, smaller tables use it with zero padded LUTs
+const MIN_DECOMP_BASE: u64 = 4;
 
-    (quantized_probe, quantized_template)
+// Rounds the bits of the dimension up, so 32 rows get base 8 rather than 4
+pub fn decomp_base_for_dimension(dimension: usize) -> u64 {
+    let bits = dimension.max(1).ceil_ilog2();
+    (1u64 << bits.div_ceil(2)).max(MIN_DECOMP_BASE)
 }
 
-pub fn get_min_decomp_base(tables: &[Vec<Vec<u64>>]) -> u64 {
-    let dimension = tables.first().expect("No tables").len();
-    let bits = dimension.ceil_ilog2();
-    1u64 << (bits / 2)
+// The base of the manifest if there is one, the smallest possible base otherwise
+pub fn get_decomp_base(config: &Config, tables: &[Vec<Vec<u64>>]) -> Result<u64> {
+    let min_base = get_min_decomp_base(tables)?;
+    match config.params.decomp_base {
+        Some(base) if base < min_base => Err(Error::UnsupportedParameters(format!("decomposition base {} of {} is below the minimum of {}", base, config.data_set_name, min_base))),
+        Some(base) => Ok(base),
+        None => Ok(min_base),
+    }
 }
 
-pub fn get_lut_output_indices(tables: &[Vec<Vec<u64>>], decomp_base: u64) -> (Vec<u64>, Vec<usize>, usize) {
+pub fn get_lut_output_indices(tables: &[Vec<Vec<u64>>], decomp_base: u64) -> Result<(Vec<u64>, Vec<usize>, usize)> {
     let max_values = tables.iter().map(|table|
-        table.first().and_then(|row| row.first()).expect("Table is empty").clone()
-    ).collect_vec();
+        table_max_value(table)
+    ).collect::<Result<Vec<_>>>()?;
     let max_total_sum: u64 = max_values.iter().sum();
 
     let decomp_lengths: Vec<usize> = max_values.iter()
@@ -52,67 +80,292 @@ pub fn get_lut_output_indices(tables: &[Vec<Vec<u64>>], decomp_base: u64) -> (Ve
         (0..*len).for_each(|v| lut_output_indices.push((idx * sum_decomp_length + v) as u64))
     });
 
-    (lut_output_indices, decomp_lengths, sum_decomp_length)
+    Ok((lut_output_indices, decomp_lengths, sum_decomp_length))
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn get_data(config: &Config, idx: usize) -> Result<((u64, u64), Vec<u64>, Vec<Vec<Vec<u64>>>, Vec<u64>, usize, MultiBitPBSParameters)> {
+    let (offset, helr_tables) = load_and_offset_helr_tables(config)?;
+    check_qbins(config, &helr_tables)?;
+    let (probe, template) = get_probe_and_template(config, idx)?;
+    let decomp_base = get_decomp_base(config, &helr_tables)?;
+    let luts = make_row_based_luts(&helr_tables, &template, decomp_base)?;
+    let (lut_output_indices, decomp_lengths, sum_block_len) = get_lut_output_indices(&helr_tables, decomp_base)?;
+    let repeated_probes = izip!(probe.iter(), decomp_lengths).map(|(p, len)| repeat_n(*p, len)).flatten().collect_vec();
+    let params = get_params_multi_bit_gpu(decomp_base)?;
+
+    println!("Running {}:", config.data_set_name);
+    println!("Decomposition base: {}", decomp_base);
+    println!("Luts: {}", lut_output_indices.len());
+    println!("Sum size: {} x {} = {} blocks", sum_block_len, probe.len(), sum_block_len * probe.len());
+
+    Ok((
+        (offset_threshold(config.reject_threshold, offset), offset_threshold(config.accept_threshold, offset)),
+        repeated_probes,
+        luts,
+        lut_output_indices,
+        sum_block_len,
+        params
+    ))
+}
+
+// Shifts a threshold on the HELR score by the table offsets. Thresholds below the lowest possible
+// score are met by every probe and map to 0.
+pub(crate) fn offset_threshold(threshold: i64, offset: i64) -> u64 {
+    (threshold - offset).max(0) as u64
 }
 
-fn decompose_template(tables: &[Vec<Vec<u64>>], template: &[u64], base: u64) -> Vec<Vec<Vec<u64>>> {
-    izip!(tables, template).map(|(table, template_idx)| {
-        let max_value = table.first().and_then(|row| row.first()).expect("Could not get max value");
-        let length = length_in_base(*max_value, base);
+fn table_max_value(table: &[Vec<u64>]) -> Result<u64> {
+    table.iter().flatten().max().cloned().ok_or_else(|| Error::shape("HELR table rows", 1, 0))
+}
+
+fn decompose_template(tables: &[Vec<Vec<u64>>], template: &[u64], base: u64) -> Result<Vec<Vec<Vec<u64>>>> {
+    if tables.len() != template.len() {
+        return Err(Error::shape("template features", tables.len(), template.len()));
+    }
+    izip!(tables, template).map(|(table, template_idx)| -> Result<Vec<Vec<u64>>> {
+        let max_value = table_max_value(table)?;
+        let length = length_in_base(max_value, base);
+
+        let row = table.get(*template_idx as usize).ok_or_else(|| Error::shape("HELR table rows", *template_idx as usize + 1, table.len()))?;
+        Ok(row.iter().map(|v| decompose_to_base(*v, base, length)).collect_vec())
+    }).collect()
+}
+
+// The LUTs of smaller tables are padded with zeros to the base * base entries of the message
+// space, the probe of such a feature never reaches the padding
+pub(crate) fn make_row_based_luts(tables: &[Vec<Vec<u64>>], template: &[u64], base: u64) -> Result<Vec<Vec<Vec<u64>>>> {
+    let decomposed = decompose_template(tables, template, base)?;
+    let lut_len = (base * base) as usize;
+    Ok(decomposed.into_iter().map(|inner| {
+        flip_vectors(inner).into_iter().map(|mut lut| {
+            lut.resize(lut_len, 0);
+            lut
+        }).collect_vec()
+    }).collect_vec())
+}
+
+// Every feature needs as many bins as its table has rows
+pub fn check_qbins(config: &Config, tables: &[Vec<Vec<u64>>]) -> Result<()> {
+    let qbins = read_qbins(&config.qbins_path)?;
+    if qbins.len() != 1 && qbins.len() != tables.len() {
+        return Err(Error::shape("qbins rows", tables.len(), qbins.len()));
+    }
+    for (idx, table) in tables.iter().enumerate() {
+        let bins = qbins[idx.min(qbins.len() - 1)].len() + 1;
+        if bins != table.len() {
+            return Err(Error::shape(&format!("quantization bins of feature {}", idx), table.len(), bins));
+        }
+    }
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<String> {
+    fs::read_to_string(path).map_err(|err| Error::io(path, err))
+}
 
-        let row = table.get(*template_idx as usize).expect("Indexing error");
-        row.iter().map(|v| decompose_to_base(*v, base, length)).collect_vec()
-    }).collect_vec()
+// Lines with their index in the file, without blank lines. Sample indices count only these lines,
+// line indices are kept for the error messages.
+fn non_blank_lines(csv: &str) -> impl Iterator<Item = (usize, &str)> {
+    csv.lines().enumerate().filter(|(_, line)| !line.trim().is_empty())
 }
 
-pub(crate) fn make_row_based_luts(tables: &[Vec<Vec<u64>>], template: &[u64], base: u64) -> Vec<Vec<Vec<u64>>> {
-    let decomposed = decompose_template(tables, template, base);
-    decomposed.into_iter().map(|inner| flip_vectors(inner)).collect_vec()
+fn parse_csv_line<T: FromStr>(path: &str, line_idx: usize, line: &str) -> Result<Vec<T>> {
+    line.split(",").enumerate().map(|(column, v)| {
+        v.trim().parse::<T>().map_err(|_| Error::parse(path, line_idx + 1, column + 1, v))
+    }).collect()
 }
 
-fn read_qbins(path: &str) -> Vec<f64> {
-    let qbins_csv = fs::read_to_string(path).expect("Unable to read file");
-    qbins_csv.split(",").map(|v| v.parse::<f64>().expect("Could not parse entry")).collect()
+// One row of increasing bin boundaries shared by all features, or one row per feature
+pub fn read_qbins(path: &str) -> Result<Vec<Vec<f64>>> {
+    let qbins_csv = read_file(path)?;
+    let rows = non_blank_lines(&qbins_csv)
+        .map(|(line_idx, line)| parse_csv_line::<f64>(path, line_idx, line))
+        .collect::<Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        return Err(Error::shape("qbins rows", 1, 0));
+    }
+    if let Some(row) = rows.iter().position(|row| row.iter().tuple_windows().any(|(a, b)| a >= b)) {
+        return Err(Error::format(path, &format!("boundaries of row {} are not strictly increasing", row + 1)));
+    }
+    Ok(rows)
 }
 
-fn read_helr_tables(path: &str, n_tables: usize) -> Vec<Vec<Vec<i64>>>{
-    (0..n_tables).map(|idx| {
-        let table_path = format!("{path}{idx}.csv");
-        let file_contents = fs::read_to_string(table_path).expect("Unable to read file");
-        file_contents.lines().map(|line|
-            line.split(",").map(|v| v.parse::<i64>().expect("could not parse entry")).collect()
+fn read_helr_tables(config: &Config) -> Result<Vec<Vec<Vec<i64>>>> {
+    (0..config.num_tables).map(|idx| -> Result<Vec<Vec<i64>>> {
+        let table_path = config.table_path(idx);
+        let file_contents = read_file(&table_path)?;
+        file_contents.lines().enumerate().map(|(line_idx, line)|
+            parse_csv_line(&table_path, line_idx, line)
         ).collect()
     }).collect()
 }
 
-fn offset_helr_tables(helr_tables: &[Vec<Vec<i64>>]) -> (i64, Vec<Vec<Vec<u64>>>) {
+fn offset_helr_tables(helr_tables: &[Vec<Vec<i64>>]) -> Result<(i64, Vec<Vec<Vec<u64>>>)> {
     let mut offset: i64 = 0;
-    let offset_helr_tables = helr_tables.iter().map(|table| {
-        let local_offset = table.first().and_then(|v| v.last()).expect("Unable to index helr table");
+    let offset_helr_tables = helr_tables.iter().map(|table| -> Result<Vec<Vec<u64>>> {
+        // With equiprobable bins this is the last entry of the first row, not so with other bins
+        let local_offset = *table.iter().flatten().min().ok_or_else(|| Error::shape("HELR table rows", 1, 0))?;
         offset += local_offset;
-        table.into_iter().map(|row| {
-            row.into_iter().map(|v| (v - local_offset) as u64).collect()
-        }).collect()
-    }).collect();
-    (offset, offset_helr_tables)
+        Ok(table.iter().map(|row| {
+            row.iter().map(|v| (v - local_offset) as u64).collect()
+        }).collect())
+    }).collect::<Result<_>>()?;
+    Ok((offset, offset_helr_tables))
+}
+
+fn parse_labelled_line(path: &str, line_idx: usize, line: &str, label_column: usize) -> Result<(String, Vec<f64>)> {
+    let columns = line.split(",").collect_vec();
+    let label = columns.get(label_column).ok_or_else(|| Error::shape("feature vector columns", label_column + 1, columns.len()))?;
+    let features = columns.iter().enumerate().filter(|(column, _)| *column != label_column).map(|(column, v)| {
+        v.trim().parse::<f64>().map_err(|_| Error::parse(path, line_idx + 1, column + 1, v))
+    }).collect::<Result<_>>()?;
+    Ok((label.trim().to_string(), features))
+}
+
+fn read_feature_vector(config: &Config, id: usize) -> Result<Vec<f64>> {
+    let path = &config.feature_vector_path;
+    let dataset_csv = read_file(path)?;
+    let dataset_lines = non_blank_lines(&dataset_csv).collect_vec();
+    let (line_idx, id_line) = dataset_lines.get(id).ok_or_else(|| Error::shape("feature vector rows", id + 1, dataset_lines.len()))?;
+    parse_labelled_line(path, *line_idx, id_line, config.label_column).map(|(_, features)| features)
+}
+
+// Subject labels and feature vectors of all samples, in file order
+pub fn read_labelled_samples(config: &Config) -> Result<(Vec<String>, Vec<Vec<f64>>)> {
+    read_labelled_csv(&config.feature_vector_path, config.label_column)
+}
+
+pub fn read_labelled_csv(path: &str, label_column: usize) -> Result<(Vec<String>, Vec<Vec<f64>>)> {
+    let dataset_csv = read_file(path)?;
+    let samples = non_blank_lines(&dataset_csv)
+        .map(|(line_idx, line)| parse_labelled_line(path, line_idx, line, label_column))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(samples.into_iter().unzip())
 }
 
-fn read_feature_vector(path: &str, id: usize) -> Vec<f64> {
-    let dataset_csv = fs::read_to_string(path).expect("Unable to read file");
-    let mut dataset_lines = dataset_csv.lines();
-    let id_line = dataset_lines.nth(id).expect("Error indexing feature vector entries");
-    let values = id_line.split(",").skip(1).map(|v| v.parse::<f64>().expect("could not parse entry")).collect();
-    values
+// Quantized feature vectors of the samples at `probe_index` and `template_index`, without noise
+pub fn get_pair(config: &Config, features: &[Vec<f64>], probe_index: usize, template_index: usize) -> Result<(Vec<u64>, Vec<u64>)> {
+    let qbins = read_qbins(&config.qbins_path)?;
+    let sample = |index: usize| features.get(index).ok_or_else(|| Error::shape("feature vector rows", index + 1, features.len()));
+    Ok((quantize_vector(sample(probe_index)?, &qbins)?, quantize_vector(sample(template_index)?, &qbins)?))
+}
+
+// All ordered (probe, template) index pairs of different samples of the same subject
+pub fn genuine_pairs(labels: &[String]) -> Vec<(usize, usize)> {
+    let mut samples_by_label: HashMap<&str, Vec<usize>> = HashMap::new();
+    labels.iter().enumerate().for_each(|(idx, label)| samples_by_label.entry(label.as_str()).or_default().push(idx));
+    let mut pairs = samples_by_label.values()
+        .flat_map(|samples| samples.iter().cartesian_product(samples.iter()).filter(|(a, b)| a != b).map(|(a, b)| (*a, *b)))
+        .collect_vec();
+    pairs.sort_unstable();
+    pairs
+}
+
+// `count` distinct random (probe, template) index pairs of samples of different subjects. Drawn
+// by rejection, as there are quadratically many of them.
+pub fn random_impostor_pairs(labels: &[String], count: usize) -> Result<Vec<(usize, usize)>> {
+    let mut samples_per_label: HashMap<&str, usize> = HashMap::new();
+    labels.iter().for_each(|label| *samples_per_label.entry(label.as_str()).or_default() += 1);
+    let mated: usize = samples_per_label.values().map(|n| n * n).sum();
+    let available = labels.len() * labels.len() - mated;
+    if count > available {
+        return Err(Error::shape("impostor pairs", count, available));
+    }
+
+    let mut rng = rand::rng();
+    let mut selected = HashSet::with_capacity(count);
+    let mut pairs = Vec::with_capacity(count);
+    while pairs.len() < count {
+        let pair = (rng.random_range(0..labels.len()), rng.random_range(0..labels.len()));
+        if labels[pair.0] != labels[pair.1] && selected.insert(pair) {
+            pairs.push(pair);
+        }
+    }
+    Ok(pairs)
 }
 
 fn distort_feature_vector(feat_vec: &[f64]) -> Vec<f64>{
-    let mut rng = rand::thread_rng();
-    feat_vec.iter().cloned().map(|v| v + rng.gen_range(-0.01..=0.01)).collect()
+    let mut rng = rand::rng();
+    feat_vec.iter().cloned().map(|v| v + rng.random_range(-0.01..=0.01)).collect()
 }
 
-fn quantize_vector(feat_vec: &[f64], qbins: &[f64]) -> Vec<u64> {
-    let l = qbins.len();
-    feat_vec.iter().map(|f| {
-        qbins.iter().position(|v| *f <= *v).unwrap_or(l) as u64
-    }).collect()
-}
\ No newline at end of file
+// Index of the bin of every feature, with the boundaries of its own row if there is one row per
+// feature
+fn quantize_vector(feat_vec: &[f64], qbins: &[Vec<f64>]) -> Result<Vec<u64>> {
+    if qbins.len() != 1 && qbins.len() != feat_vec.len() {
+        return Err(Error::shape("qbins rows", feat_vec.len(), qbins.len()));
+    }
+    Ok(feat_vec.iter().enumerate().map(|(idx, f)| {
+        let row = &qbins[idx.min(qbins.len() - 1)];
+        row.iter().position(|v| *f <= *v).unwrap_or(row.len()) as u64
+    }).collect())
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::common::config::ParamOverrides;
+
+    // Subject of every sample of the fixture, in file order, and its quantized features
+    pub(crate) const FIXTURE_LABELS: [&str; 5] = ["alice", "bob", "alice", "bob", "carol"];
+    pub(crate) const FIXTURE_BINS: [[u64; 2]; 5] = [[0, 1], [1, 0], [0, 1], [1, 0], [1, 1]];
+
+    // Two features with two bins each and a feature vector file with blank lines between samples
+    pub(crate) fn fixture_config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("data_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+        fs::write(path("features.csv"), "alice,-1.0,0.5\n\nbob,0.5,-0.5\nalice,-0.8,0.7\n  \nbob,0.6,-0.4\ncarol,1.0,1.0\n\n").unwrap();
+        fs::write(path("qbins.csv"), "0.0\n").unwrap();
+        for idx in 0..2 {
+            fs::write(path(&format!("HELR{}.csv", idx)), "3,-2\n-2,3\n").unwrap();
+        }
+        Config {
+            data_set_name: "fixture".to_string(),
+            feature_vector_path: path("features.csv"),
+            label_column: 0,
+            tables_dir: dir.to_string_lossy().to_string(),
+            table_prefix: "HELR".to_string(),
+            qbins_path: path("qbins.csv"),
+            num_tables: 2,
+            reject_threshold: 0,
+            accept_threshold: 4,
+            params: ParamOverrides::default(),
+        }
+    }
+
+    #[test]
+    fn sample_indices_skip_blank_lines() {
+        let config = fixture_config("indices");
+        let (labels, features) = read_labelled_samples(&config).unwrap();
+        assert_eq!(labels, FIXTURE_LABELS);
+        let qbins = read_qbins(&config.qbins_path).unwrap();
+        for (idx, sample) in features.iter().enumerate() {
+            assert_eq!(&read_feature_vector(&config, idx).unwrap(), sample);
+            assert_eq!(quantize_vector(sample, &qbins).unwrap(), FIXTURE_BINS[idx]);
+            assert_eq!(get_probe_and_template(&config, idx).unwrap().1, FIXTURE_BINS[idx]);
+        }
+        assert!(matches!(read_feature_vector(&config, 5), Err(Error::Shape { .. })));
+    }
+
+    #[test]
+    fn pairs_follow_the_labels() {
+        let labels = FIXTURE_LABELS.map(String::from);
+        assert_eq!(genuine_pairs(&labels), [(0, 2), (1, 3), (2, 0), (3, 1)]);
+        let impostors = random_impostor_pairs(&labels, 16).unwrap();
+        assert_eq!(impostors.iter().collect::<HashSet<_>>().len(), 16);
+        assert!(impostors.iter().all(|(a, b)| labels[*a] != labels[*b]));
+        assert!(random_impostor_pairs(&labels, 17).is_err());
+    }
+
+    #[test]
+    fn decomp_base_of_small_dimensions() {
+        for dimension in 0..=16 {
+            assert_eq!(decomp_base_for_dimension(dimension), 4, "dimension {}", dimension);
+        }
+        assert_eq!(decomp_base_for_dimension(17), 8);
+        assert_eq!(decomp_base_for_dimension(32), 8);
+        assert_eq!(decomp_base_for_dimension(64), 8);
+        assert_eq!(decomp_base_for_dimension(65), 16);
+    }
+}
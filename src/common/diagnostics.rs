@@ -0,0 +1,37 @@
+use std::fs;
+use serde::Serialize;
+use crate::common::ciphertexts::Decision;
+use crate::common::error::{Error, Result};
+use crate::common::reference::ReferenceTrace;
+
+// Decrypted intermediate values of one encrypted run, next to the plaintext reference. It holds
+// the biometric score, so it is only ever produced in diagnostic mode and never by the server.
+#[derive(Serialize)]
+pub struct DiagnosticTrace {
+    pub dataset: String,
+    pub backend: String,
+    // PBS output blocks after placement, i.e. the input of the block sum
+    pub pbs_outputs: Vec<u64>,
+    // Radix blocks of the sum, the input of the comparison
+    pub sum_blocks: Vec<u64>,
+    pub comparison_input: u64,
+    pub reject_threshold: u64,
+    pub accept_threshold: u64,
+    pub decision: Decision,
+    pub reference: ReferenceTrace,
+    pub first_mismatch: Option<String>,
+    pub pbs_seconds: f64,
+    pub total_seconds: f64,
+}
+
+impl DiagnosticTrace {
+    pub fn save(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|err| Error::format("diagnostic trace", &err.to_string()))?;
+        fs::write(path, json).map_err(|err| Error::io(path, err))
+    }
+}
+
+// Recombines little endian radix blocks
+pub fn radix_value(blocks: &[u64], base: u64) -> u64 {
+    blocks.iter().rev().fold(0u64, |acc, digit| acc * base + digit)
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    Parse {
+        file: String,
+        line: usize,
+        column: usize,
+        value: String,
+    },
+    Shape {
+        context: String,
+        expected: usize,
+        found: usize,
+    },
+    UnsupportedParameters(String),
+    Format {
+        origin: String,
+        reason: String,
+    },
+    ParameterMismatch(String),
+    InvalidSubject(String),
+    Protocol(String),
+    UnknownSubject(String),
+    UnknownDataset(String),
+    Rotation {
+        subject_id: String,
+        reason: String,
+    },
+    Audit {
+        path: String,
+        reason: String,
+    },
+    // `limited` names the subject or client that made too many attempts
+    RateLimited {
+        limited: String,
+        retry_after: u64,
+    },
+    BackendNotEnabled(String),
+    // The evaluation of a request panicked, the server carries on with the next connection
+    Evaluation(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: &str, source: io::Error) -> Self {
+        Error::Io { path: path.to_string(), source }
+    }
+
+    pub fn parse(file: &str, line: usize, column: usize, value: &str) -> Self {
+        Error::Parse { file: file.to_string(), line, column, value: value.to_string() }
+    }
+
+    pub fn format(origin: &str, reason: &str) -> Self {
+        Error::Format { origin: origin.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn shape(context: &str, expected: usize, found: usize) -> Self {
+        Error::Shape { context: context.to_string(), expected, found }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "I/O error on {}: {}", path, source),
+            Error::Parse { file, line, column, value } => write!(f, "could not parse entry '{}' in {} at line {}, column {}", value, file, line, column),
+            Error::Shape { context, expected, found } => write!(f, "shape mismatch in {}: expected {}, found {}", context, expected, found),
+            Error::UnsupportedParameters(msg) => write!(f, "unsupported parameters: {}", msg),
+            Error::Format { origin, reason } => write!(f, "invalid format in {}: {}", origin, reason),
+            Error::ParameterMismatch(origin) => write!(f, "parameters of {} do not match the configuration in use", origin),
+            Error::InvalidSubject(subject_id) => write!(f, "invalid subject id '{}'", subject_id),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::UnknownSubject(subject_id) => write!(f, "no template enrolled for subject '{}'", subject_id),
+            Error::UnknownDataset(name) => write!(f, "no dataset named '{}' in the manifest", name),
+            Error::Rotation { subject_id, reason } => write!(f, "key rotation of subject '{}' failed: {}", subject_id, reason),
+            Error::Audit { path, reason } => write!(f, "audit log {} failed verification: {}", path, reason),
+            Error::RateLimited { limited, retry_after } => write!(f, "too many attempts for {}, retry in {}s", limited, retry_after),
+            Error::BackendNotEnabled(backend) => write!(f, "the {} backend is not enabled, rebuild with the matching cargo feature", backend),
+            Error::Evaluation(request) => write!(f, "the evaluation of the {} failed", request),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use tfhe::core_crypto::commons::generators::DeterministicSeeder;
+use tfhe::core_crypto::prelude::*;
+use tfhe::integer;
+use tfhe::shortint::ciphertext::MaxDegree;
+use tfhe::shortint::server_key::ShortintBootstrappingKey;
+use tfhe::shortint::{MultiBitPBSParameters, PBSOrder};
+use crate::common::keys::{ClientKey, ServerKey};
+
+pub fn genkeys_multibit(parameters: MultiBitPBSParameters) -> (ClientKey, ServerKey) {
+    //Init seeders and rnd generators
+    let mut root_seeder = new_seeder();
+
+    let mut seeder =
+        DeterministicSeeder::<ActivatedRandomGenerator>::new(root_seeder.seed());
+
+    let mut secret_generator: SecretRandomGenerator<ActivatedRandomGenerator> = SecretRandomGenerator::new(seeder.seed());
+    let mut encryption_generator = EncryptionRandomGenerator::new(
+        seeder.seed(),
+        &mut seeder,
+    );
+
+    // Gen Client keys
+    // generate the lwe secret key
+    let lwe_secret_key = allocate_and_generate_new_binary_lwe_secret_key(
+        parameters.lwe_dimension,
+        &mut secret_generator,
+    );
+
+    // generate the rlwe secret key
+    let glwe_secret_key = allocate_and_generate_new_binary_glwe_secret_key(
+        parameters.glwe_dimension,
+        parameters.polynomial_size,
+        &mut secret_generator,
+    );
+
+    // Gen Server Keys
+    // Generate a regular keyset, the backends convert it to their own representation
+    let h_bootstrap_key: LweMultiBitBootstrapKeyOwned<u64> =
+        par_allocate_and_generate_new_lwe_multi_bit_bootstrap_key(
+            &lwe_secret_key.as_view(),
+            &glwe_secret_key,
+            parameters.pbs_base_log,
+            parameters.pbs_level,
+            parameters.grouping_factor,
+            parameters.glwe_noise_distribution,
+            parameters.ciphertext_modulus,
+            &mut encryption_generator,
+        );
+
+    // Creation of the key switching key
+    let h_key_switching_key = allocate_and_generate_new_lwe_keyswitch_key(
+        &glwe_secret_key.as_lwe_secret_key(),
+        &lwe_secret_key.as_view(),
+        parameters.ks_base_log,
+        parameters.ks_level,
+        parameters.lwe_noise_distribution,
+        parameters.ciphertext_modulus,
+        &mut encryption_generator,
+    );
+
+    let delta = get_delta(parameters);
+
+    let client_key = ClientKey {
+        encryption_generator,
+        lwe_secret_key,
+        glwe_secret_key,
+        delta,
+        params: parameters,
+    };
+    let server_key = ServerKey {
+        bootstrap_key: h_bootstrap_key,
+        key_switching_key: h_key_switching_key,
+        params: parameters,
+    };
+
+    (client_key, server_key)
+}
+
+pub fn get_delta(parameters: MultiBitPBSParameters) -> u64 {
+    //The delta is the one defined by the parameters
+    (1_u64 << 63)
+        / (parameters.message_modulus.0 * parameters.carry_modulus.0)
+        as u64
+}
+
+// Fresh encryption randomness, e.g. for a client key loaded from disk
+pub fn new_encryption_generator() -> EncryptionRandomGenerator<ActivatedRandomGenerator> {
+    let mut root_seeder = new_seeder();
+    let mut seeder =
+        DeterministicSeeder::<ActivatedRandomGenerator>::new(root_seeder.seed());
+    EncryptionRandomGenerator::new(
+        seeder.seed(),
+        &mut seeder,
+    )
+}
+
+// Keys for the host side integer operations and the CPU PBS, used by the CPU backend
+pub fn host_server_keys(h_bootstrap_key: &LweMultiBitBootstrapKeyOwned<u64>, h_key_switching_key: &LweKeyswitchKeyOwned<u64>, parameters: MultiBitPBSParameters) -> (FourierLweMultiBitBootstrapKeyOwned, integer::ServerKey) {
+    let fourier_bsk = fourier_bootstrap_key(h_bootstrap_key);
+
+    // The radix sum and comparison run on the integer server key, which wraps the same keys
+    let server_key = make_integer_server_key(fourier_bsk.clone(), h_key_switching_key.clone(), parameters);
+
+    (fourier_bsk, server_key)
+}
+
+// Only the integer server key, for the GPU ranking fallback, which has no use for a second copy of
+// the Fourier bootstrap key
+pub fn host_integer_key(h_bootstrap_key: &LweMultiBitBootstrapKeyOwned<u64>, h_key_switching_key: &LweKeyswitchKeyOwned<u64>, parameters: MultiBitPBSParameters) -> integer::ServerKey {
+    make_integer_server_key(fourier_bootstrap_key(h_bootstrap_key), h_key_switching_key.clone(), parameters)
+}
+
+// Converts the bootstrap key to the fourier domain used by the CPU PBS
+fn fourier_bootstrap_key(h_bootstrap_key: &LweMultiBitBootstrapKeyOwned<u64>) -> FourierLweMultiBitBootstrapKeyOwned {
+    let mut fourier_bsk = FourierLweMultiBitBootstrapKey::new(
+        h_bootstrap_key.input_lwe_dimension(),
+        h_bootstrap_key.glwe_size(),
+        h_bootstrap_key.polynomial_size(),
+        h_bootstrap_key.decomposition_base_log(),
+        h_bootstrap_key.decomposition_level_count(),
+        h_bootstrap_key.grouping_factor(),
+    );
+    par_convert_standard_lwe_multi_bit_bootstrap_key_to_fourier(h_bootstrap_key, &mut fourier_bsk);
+    fourier_bsk
+}
+
+pub fn make_integer_server_key(fourier_bsk: FourierLweMultiBitBootstrapKeyOwned, key_switching_key: LweKeyswitchKeyOwned<u64>, parameters: MultiBitPBSParameters) -> integer::ServerKey {
+    let bootstrapping_key = ShortintBootstrappingKey::MultiBit {
+        fourier_bsk,
+        thread_count: ThreadCount(parameters.grouping_factor.ggsw_per_multi_bit_element().0),
+        deterministic_execution: parameters.deterministic_execution,
+    };
+
+    let shortint_key = tfhe::shortint::ServerKey::from_raw_parts(
+        key_switching_key,
+        bootstrapping_key,
+        parameters.message_modulus,
+        parameters.carry_modulus,
+        MaxDegree::from_msg_carry_modulus(parameters.message_modulus, parameters.carry_modulus),
+        parameters.max_noise_level,
+        parameters.ciphertext_modulus,
+        PBSOrder::KeyswitchBootstrap,
+    );
+
+    integer::ServerKey::new_radix_server_key_from_shortint(shortint_key)
+}
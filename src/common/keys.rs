@@ -0,0 +1,139 @@
+use tfhe::core_crypto::prelude::*;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::Result;
+use crate::common::keygen::{get_delta, new_encryption_generator};
+use crate::common::serialization::{self, FileKind};
+use crate::common::tfhe_utils::{decrypt_decode_list, decrypt_round_glwe_list, encode_encrypt_list, encrypt_glwe_plaintexts, make_encrypted_accumulator_list, short_params};
+use crate::common::util::decompose_to_base;
+
+const CLIENT_KEY_FILE: FileKind = FileKind { magic: *b"SBCK", version: 1 };
+const SERVER_KEY_FILE: FileKind = FileKind { magic: *b"SBSK", version: 1 };
+
+// Secret material, only held by the party that encrypts probes and templates and reads decisions
+pub struct ClientKey {
+    pub(crate) encryption_generator: EncryptionRandomGenerator<ActivatedRandomGenerator>,
+    pub(crate) lwe_secret_key: LweSecretKeyOwned<u64>,
+    pub(crate) glwe_secret_key: GlweSecretKeyOwned<u64>,
+    pub(crate) delta: u64,
+    pub(crate) params: MultiBitPBSParameters,
+}
+
+// Public evaluation keys, the only key material handed to the server
+pub struct ServerKey {
+    pub(crate) bootstrap_key: LweMultiBitBootstrapKeyOwned<u64>,
+    pub(crate) key_switching_key: LweKeyswitchKeyOwned<u64>,
+    pub(crate) params: MultiBitPBSParameters,
+}
+
+impl ClientKey {
+    pub fn params(&self) -> MultiBitPBSParameters {
+        self.params
+    }
+
+    pub fn delta(&self) -> u64 {
+        self.delta
+    }
+
+    // Encrypts under the small LWE key, as expected at the input of the PBS
+    pub fn encrypt_list(&mut self, values: &[u64]) -> LweCiphertextListOwned<u64> {
+        encode_encrypt_list(
+            values,
+            self.delta,
+            self.lwe_secret_key.as_view(),
+            self.params.lwe_noise_distribution,
+            &mut self.encryption_generator,
+            &short_params(self.params),
+        )
+    }
+
+    // Encrypts `value` as a radix of `blocks` digits under the large key, the key of the radix
+    // sum computed by the server
+    pub fn encrypt_radix(&mut self, value: u64, blocks: usize) -> LweCiphertextListOwned<u64> {
+        let digits = decompose_to_base(value, self.params.message_modulus.0 as u64, blocks);
+        self.encrypt_radix_digits(&digits)
+    }
+
+    // Encrypts already decomposed radix blocks, least significant first
+    pub fn encrypt_radix_digits(&mut self, digits: &[u64]) -> LweCiphertextListOwned<u64> {
+        encode_encrypt_list(
+            digits,
+            self.delta,
+            self.glwe_secret_key.as_lwe_secret_key(),
+            self.params.glwe_noise_distribution,
+            &mut self.encryption_generator,
+            &short_params(self.params),
+        )
+    }
+
+    pub fn encrypt_luts<F>(&mut self, fs: &[F]) -> GlweCiphertextListOwned<u64>
+    where
+        F: Fn(u64) -> u64,
+    {
+        make_encrypted_accumulator_list(
+            fs,
+            &short_params(self.params),
+            self.glwe_secret_key.as_view(),
+            self.params.glwe_noise_distribution,
+            &mut self.encryption_generator,
+        )
+    }
+
+    // Rounded plaintext accumulators of encrypted LUTs, only meant for re-encryption under a new key
+    pub fn decrypt_luts(&self, luts: &GlweCiphertextListOwned<u64>) -> Vec<u64> {
+        decrypt_round_glwe_list(luts, self.delta, self.glwe_secret_key.as_view())
+    }
+
+    // Encrypts plaintext accumulators as returned by decrypt_luts
+    pub fn encrypt_lut_plaintexts(&mut self, plaintexts: &[u64]) -> GlweCiphertextListOwned<u64> {
+        encrypt_glwe_plaintexts(
+            plaintexts,
+            &short_params(self.params),
+            self.glwe_secret_key.as_view(),
+            self.params.glwe_noise_distribution,
+            &mut self.encryption_generator,
+        )
+    }
+
+    // The file holds the LWE and GLWE secret keys in plaintext, it is only readable by its owner.
+    // Fails if `path` exists: the templates enrolled under a replaced key can not be decrypted.
+    pub fn save(&self, path: &str) -> Result<()> {
+        serialization::save_private(path, CLIENT_KEY_FILE, self.params, &(&self.lwe_secret_key, &self.glwe_secret_key))
+    }
+
+    // Fails if the key was generated for other parameters than `params`
+    pub fn load(path: &str, params: MultiBitPBSParameters) -> Result<Self> {
+        let (params, (lwe_secret_key, glwe_secret_key)) = serialization::load::<(LweSecretKeyOwned<u64>, GlweSecretKeyOwned<u64>)>(path, CLIENT_KEY_FILE, Some(params))?;
+        Ok(ClientKey {
+            encryption_generator: new_encryption_generator(),
+            lwe_secret_key,
+            glwe_secret_key,
+            delta: get_delta(params),
+            params,
+        })
+    }
+
+    // Decrypts ciphertexts under the large key, i.e. the outputs of the server computation
+    pub fn decrypt_list(&self, input_cts: LweCiphertextListView<u64>) -> Vec<u64> {
+        decrypt_decode_list(input_cts, self.delta, self.glwe_secret_key.as_lwe_secret_key())
+    }
+}
+
+impl ServerKey {
+    pub fn params(&self) -> MultiBitPBSParameters {
+        self.params
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        serialization::save(path, SERVER_KEY_FILE, self.params, &(&self.bootstrap_key, &self.key_switching_key))
+    }
+
+    // Fails if the key was generated for other parameters than `params`
+    pub fn load(path: &str, params: MultiBitPBSParameters) -> Result<Self> {
+        let (params, (bootstrap_key, key_switching_key)) = serialization::load::<(LweMultiBitBootstrapKeyOwned<u64>, LweKeyswitchKeyOwned<u64>)>(path, SERVER_KEY_FILE, Some(params))?;
+        Ok(ServerKey {
+            bootstrap_key,
+            key_switching_key,
+            params,
+        })
+    }
+}
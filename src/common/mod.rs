@@ -1,4 +1,20 @@
 pub mod config;
 pub mod util;
 pub mod data;
-pub mod tfhe_utils;
\ No newline at end of file
+pub mod tfhe_utils;
+pub mod keygen;
+pub mod keys;
+pub mod backend;
+pub mod auth;
+pub mod reference;
+pub mod diagnostics;
+pub mod error;
+pub mod serialization;
+pub mod ciphertexts;
+pub mod store;
+pub mod radix;
+pub mod ranking;
+pub mod rotation;
+pub mod audit;
+pub mod training;
+pub mod qbins;
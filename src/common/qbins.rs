@@ -0,0 +1,210 @@
+use std::fs;
+use std::str::FromStr;
+use itertools::Itertools;
+use crate::common::data::decomp_base_for_dimension;
+use crate::common::error::{Error, Result};
+use crate::common::tfhe_utils::get_params_multi_bit_gpu;
+
+// Where the bin boundaries come from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinSource {
+    // Quantiles of the normal distribution, standard or fitted to the feature
+    Gaussian,
+    // Quantiles of the training values
+    Empirical,
+}
+
+impl FromStr for BinSource {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "gaussian" => Ok(BinSource::Gaussian),
+            "empirical" => Ok(BinSource::Empirical),
+            _ => Err(format!("unknown bin source {}, expected gaussian or empirical", name)),
+        }
+    }
+}
+
+// Decomposition base of tables with `num_bins` rows, fails if no parameter set supports it
+pub fn check_bin_count(num_bins: usize) -> Result<u64> {
+    if num_bins < 2 {
+        return Err(Error::shape("quantization bins", 2, num_bins));
+    }
+    let decomp_base = decomp_base_for_dimension(num_bins);
+    get_params_multi_bit_gpu(decomp_base)?;
+    Ok(decomp_base)
+}
+
+// Boundaries of `num_bins` bins of equal probability under the standard normal distribution
+pub fn gaussian_bins(num_bins: usize) -> Vec<f64> {
+    (1..num_bins).map(|idx| inverse_normal_cdf(idx as f64 / num_bins as f64)).collect()
+}
+
+// Boundaries of `num_bins` bins holding the same share of `values`, linearly interpolated between
+// neighbouring values
+pub fn empirical_bins(values: &[f64], num_bins: usize) -> Result<Vec<f64>> {
+    if values.len() < num_bins {
+        return Err(Error::shape("training values for the quantiles", num_bins, values.len()));
+    }
+    let sorted = values.iter().copied().sorted_by(f64::total_cmp).collect_vec();
+    let bins = (1..num_bins).map(|idx| {
+        let position = idx as f64 / num_bins as f64 * (sorted.len() - 1) as f64;
+        let lower = position.floor() as usize;
+        let fraction = position - lower as f64;
+        sorted[lower] + fraction * (sorted[(lower + 1).min(sorted.len() - 1)] - sorted[lower])
+    }).collect_vec();
+    if bins.iter().tuple_windows().any(|(a, b)| a >= b) {
+        return Err(Error::format("training values", &format!("too many repeated values for {} distinct bins", num_bins)));
+    }
+    Ok(bins)
+}
+
+// One row of boundaries shared by all features, or one row per feature with `per_feature`. Shared
+// Gaussian bins are the standard normal quantiles and assume standardized features, per feature
+// ones are fitted to the mean and standard deviation of the feature.
+pub fn generate_qbins(features: &[Vec<f64>], num_bins: usize, source: BinSource, per_feature: bool) -> Result<Vec<Vec<f64>>> {
+    check_bin_count(num_bins)?;
+    if !per_feature {
+        return match source {
+            BinSource::Gaussian => Ok(vec![gaussian_bins(num_bins)]),
+            BinSource::Empirical => Ok(vec![empirical_bins(&features.iter().flatten().copied().collect_vec(), num_bins)?]),
+        };
+    }
+
+    let dimension = features.first().ok_or_else(|| Error::shape("training vectors", 1, 0))?.len();
+    if let Some(vector) = features.iter().find(|vector| vector.len() != dimension) {
+        return Err(Error::shape("training vector features", dimension, vector.len()));
+    }
+    (0..dimension).map(|feature| {
+        let values = features.iter().map(|vector| vector[feature]).collect_vec();
+        match source {
+            BinSource::Gaussian => {
+                let mean = values.iter().sum::<f64>() / values.len() as f64;
+                let std_dev = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1).max(1) as f64).sqrt();
+                if std_dev <= 0.0 {
+                    return Err(Error::format("training vectors", &format!("feature {} is constant", feature)));
+                }
+                Ok(gaussian_bins(num_bins).into_iter().map(|bound| mean + std_dev * bound).collect())
+            }
+            BinSource::Empirical => empirical_bins(&values, num_bins),
+        }
+    }).collect()
+}
+
+// Comma separated boundaries, one row per line, as read by the data module
+pub fn write_qbins(path: &str, rows: &[Vec<f64>]) -> Result<()> {
+    let content = rows.iter().map(|row| row.iter().map(|bound| format!("{:.15}", bound)).join(",")).join("\n");
+    fs::write(path, content + "\n").map_err(|err| Error::io(path, err))
+}
+
+// Acklam's rational approximation, relative error below 1.2e-9
+fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2, 1.38357751867269e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2, 6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    const P_LOW: f64 = 0.02425;
+
+    let tail = |q: f64| (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::data::read_qbins;
+
+    fn assert_close(found: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(found.len(), expected.len());
+        for (idx, (f, e)) in found.iter().zip(expected).enumerate() {
+            assert!((f - e).abs() <= tolerance, "boundary {}: {} instead of {}", idx, f, e);
+        }
+    }
+
+    #[test]
+    fn gaussian_bins_reproduce_the_bundled_files() {
+        for dataset in ["PUT", "FRGC", "BMDB"] {
+            let path = format!("{}/data/lookupTables/{}/{}_qbins.csv", env!("CARGO_MANIFEST_DIR"), dataset, dataset);
+            let bundled = read_qbins(&path).unwrap();
+            assert_eq!(bundled.len(), 1);
+            assert_close(&gaussian_bins(bundled[0].len() + 1), &bundled[0], 1e-8);
+        }
+    }
+
+    #[test]
+    fn empirical_bins_interpolate_between_values() {
+        let values = [8.0, 0.0, 6.0, 1.0, 2.0, 7.0, 3.0, 5.0, 4.0];
+        assert_close(&empirical_bins(&values, 4).unwrap(), &[2.0, 4.0, 6.0], 1e-12);
+        // Fewer values than bins
+        assert!(empirical_bins(&values, 16).is_err());
+    }
+
+    #[test]
+    fn empirical_bins_of_repeated_values() {
+        // Ties are fine as long as the boundaries stay distinct
+        assert_close(&empirical_bins(&[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0], 2).unwrap(), &[1.5], 1e-12);
+        assert!(empirical_bins(&[1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0], 4).is_err());
+        assert!(empirical_bins(&[3.0; 16], 4).is_err());
+    }
+
+    #[test]
+    fn constant_features_are_refused() {
+        let features = (0..8).map(|idx| vec![idx as f64, 100.0]).collect_vec();
+        assert!(generate_qbins(&features, 4, BinSource::Gaussian, true).is_err());
+        assert!(generate_qbins(&features, 4, BinSource::Empirical, true).is_err());
+        // Pooled over all features the constant one only repeats a value
+        assert!(generate_qbins(&features, 4, BinSource::Empirical, false).is_ok());
+    }
+
+    #[test]
+    fn per_feature_bounds_follow_every_feature() {
+        let features = (0..64).map(|idx| vec![idx as f64, 100.0 + 10.0 * idx as f64]).collect_vec();
+        for source in [BinSource::Gaussian, BinSource::Empirical] {
+            let rows = generate_qbins(&features, 8, source, true).unwrap();
+            assert_eq!(rows.len(), 2);
+            for (feature, row) in rows.iter().enumerate() {
+                assert_eq!(row.len(), 7);
+                assert!(row.iter().tuple_windows().all(|(a, b)| a < b));
+                let (min, max) = features.iter().map(|vector| vector[feature]).minmax().into_option().unwrap();
+                assert!(row[0] > min && row[6] < max, "{:?} feature {} outside {} to {}", source, feature, min, max);
+                // The middle boundary is the mean, resp. the median, of the feature
+                assert!((row[3] - (min + max) / 2.0).abs() < 1e-9);
+            }
+            let scaled = rows[0].iter().map(|bound| 100.0 + 10.0 * bound).collect_vec();
+            assert_close(&rows[1], &scaled, 1e-9);
+        }
+        let shared = generate_qbins(&features, 8, BinSource::Gaussian, false).unwrap();
+        assert_eq!(shared, vec![gaussian_bins(8)]);
+    }
+
+    #[test]
+    fn bin_counts_need_a_parameter_set() {
+        assert!(check_bin_count(1).is_err());
+        assert_eq!(check_bin_count(2).unwrap(), 4);
+        assert_eq!(check_bin_count(16).unwrap(), 4);
+        assert_eq!(check_bin_count(17).unwrap(), 8);
+        assert_eq!(check_bin_count(64).unwrap(), 8);
+        assert!(check_bin_count(65).is_err());
+    }
+
+    #[test]
+    fn written_bins_read_back() {
+        let path = std::env::temp_dir().join(format!("qbins_{}.csv", std::process::id())).to_string_lossy().to_string();
+        let rows = vec![gaussian_bins(4), vec![-1.0, 0.25, 3.5]];
+        write_qbins(&path, &rows).unwrap();
+        let read = read_qbins(&path).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_close(&read[0], &rows[0], 1e-14);
+        assert_close(&read[1], &rows[1], 0.0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+use tfhe::core_crypto::prelude::*;
+use tfhe::integer::{IntegerCiphertext, RadixCiphertext, ServerKey};
+use tfhe::shortint::ciphertext::{Degree, NoiseLevel};
+use tfhe::shortint::{Ciphertext, MultiBitPBSParameters, PBSOrder};
+use crate::common::backend::ComparisonType;
+use crate::common::error::{Error, Result};
+
+// Wraps a slice of raw LWE blocks into a radix ciphertext, each block holding one digit
+pub fn lwe_list_to_radix(input: LweCiphertextListView<u64>, params: MultiBitPBSParameters) -> RadixCiphertext {
+    let blocks = input.iter().map(|ct| {
+        Ciphertext::new(
+            LweCiphertext::from_container(ct.as_ref().to_vec(), ct.ciphertext_modulus()),
+            Degree::new(params.message_modulus.0 - 1),
+            NoiseLevel::NOMINAL,
+            params.message_modulus,
+            params.carry_modulus,
+            PBSOrder::KeyswitchBootstrap,
+        )
+    }).collect::<Vec<_>>();
+    RadixCiphertext::from(blocks)
+}
+
+pub fn radix_to_lwe_list(input: &RadixCiphertext) -> Result<LweCiphertextListOwned<u64>> {
+    let first = &input.blocks().first().ok_or_else(|| Error::shape("radix ciphertext blocks", 1, 0))?.ct;
+    let lwe_size = first.lwe_size();
+    let ciphertext_modulus = first.ciphertext_modulus();
+    let data = input.blocks().iter().flat_map(|block| block.ct.as_ref().iter().copied()).collect::<Vec<_>>();
+    Ok(LweCiphertextList::from_container(data, lwe_size, ciphertext_modulus))
+}
+
+pub fn block_to_lwe_list(input: &Ciphertext) -> LweCiphertextListOwned<u64> {
+    LweCiphertextList::from_container(input.ct.as_ref().to_vec(), input.ct.lwe_size(), input.ct.ciphertext_modulus())
+}
+
+// Radix against radix comparison on the host, used by both backends
+pub fn comparison_ct(
+    lhs: &LweCiphertextListOwned<u64>,
+    rhs: &LweCiphertextListOwned<u64>,
+    server_key: &ServerKey,
+    op: ComparisonType,
+    params: MultiBitPBSParameters) -> LweCiphertextListOwned<u64>
+{
+    let lhs = lwe_list_to_radix(lhs.as_view(), params);
+    let rhs = lwe_list_to_radix(rhs.as_view(), params);
+    let result = match op {
+        ComparisonType::GT => server_key.unchecked_gt_parallelized(&lhs, &rhs),
+        ComparisonType::GE => server_key.unchecked_ge_parallelized(&lhs, &rhs),
+        ComparisonType::LT => server_key.unchecked_lt_parallelized(&lhs, &rhs),
+        ComparisonType::LE => server_key.unchecked_le_parallelized(&lhs, &rhs),
+        ComparisonType::EQ => server_key.unchecked_eq_parallelized(&lhs, &rhs),
+        ComparisonType::NE => server_key.unchecked_ne_parallelized(&lhs, &rhs),
+    };
+    block_to_lwe_list(&result.into_raw_parts())
+}
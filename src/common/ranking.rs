@@ -0,0 +1,119 @@
+use itertools::Itertools;
+use rayon::prelude::*;
+use tfhe::core_crypto::prelude::*;
+use tfhe::integer::prelude::ServerKeyDefaultCMux;
+use tfhe::integer::{BooleanBlock, RadixCiphertext, ServerKey};
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::{Error, Result};
+use crate::common::radix::{lwe_list_to_radix, radix_to_lwe_list};
+
+// One enrolled subject during the ranking. `taken` marks subjects already returned by an
+// earlier round, they lose against every subject that is still available.
+#[derive(Clone)]
+struct Candidate {
+    score: RadixCiphertext,
+    index: RadixCiphertext,
+    // Single block holding the outcome of the threshold comparisons
+    decision: RadixCiphertext,
+    taken: BooleanBlock,
+}
+
+// Number of radix blocks needed to hold the indices 0..count
+pub fn index_blocks(count: usize, message_modulus: u64) -> usize {
+    let mut blocks = 1;
+    let mut capacity = message_modulus;
+    while capacity < count as u64 {
+        capacity *= message_modulus;
+        blocks += 1;
+    }
+    blocks
+}
+
+// top_k on raw LWE lists: one radix score and one decision block per subject, returning the
+// radix index and decision block of each selected subject
+pub fn top_k_lwe(
+    scores: &[LweCiphertextListOwned<u64>],
+    decisions: &[LweCiphertextListOwned<u64>],
+    k: usize,
+    server_key: &ServerKey,
+    params: MultiBitPBSParameters) -> Result<Vec<(LweCiphertextListOwned<u64>, LweCiphertextListOwned<u64>)>>
+{
+    let scores = scores.iter().map(|score| lwe_list_to_radix(score.as_view(), params)).collect_vec();
+    let decisions = decisions.iter().map(|decision| lwe_list_to_radix(decision.as_view(), params)).collect_vec();
+    top_k(scores, decisions, k, params.message_modulus.0 as u64, server_key)?
+        .into_iter()
+        .map(|(index, decision)| Ok((radix_to_lwe_list(&index)?, radix_to_lwe_list(&decision)?)))
+        .collect()
+}
+
+// Encrypted top-k over the radix scores of the gallery, returning the encrypted index and
+// decision of the `k` best subjects, best first. Ties go to the lower index.
+// Only the selected indices and decisions leave this function, never a score.
+pub fn top_k(
+    scores: Vec<RadixCiphertext>,
+    decisions: Vec<RadixCiphertext>,
+    k: usize,
+    message_modulus: u64,
+    server_key: &ServerKey) -> Result<Vec<(RadixCiphertext, RadixCiphertext)>>
+{
+    if scores.len() != decisions.len() {
+        return Err(Error::shape("ranked decisions", scores.len(), decisions.len()));
+    }
+    if k == 0 || k > scores.len() {
+        return Err(Error::shape("ranked candidates", scores.len(), k));
+    }
+    let num_index_blocks = index_blocks(scores.len(), message_modulus);
+    let mut candidates = scores.into_iter().zip(decisions).enumerate()
+        .map(|(i, (score, decision))| Candidate {
+            score,
+            index: server_key.create_trivial_radix(i as u64, num_index_blocks),
+            decision,
+            taken: server_key.create_trivial_boolean_block(false),
+        })
+        .collect_vec();
+
+    let mut result = Vec::with_capacity(k);
+    for _ in 0..k {
+        // Tournament tree, the selection is associative so rayon may group the pairs freely
+        let best = candidates.par_iter()
+            .cloned()
+            .reduce_with(|a, b| select(a, b, server_key))
+            .ok_or_else(|| Error::shape("ranked subjects", 1, 0))?;
+
+        candidates.par_iter_mut().enumerate().for_each(|(i, candidate)| {
+            let selected = server_key.scalar_eq_parallelized(&best.index, i as u64);
+            candidate.taken = server_key.boolean_bitor(&candidate.taken, &selected);
+        });
+        result.push((best.index, best.decision));
+    }
+    Ok(result)
+}
+
+// The better of two candidates: available before taken, then the higher score, then `a`
+fn select(a: Candidate, b: Candidate, server_key: &ServerKey) -> Candidate {
+    let (higher, b_available) = rayon::join(
+        || server_key.unchecked_gt_parallelized(&b.score, &a.score),
+        || server_key.boolean_bitnot(&b.taken),
+    );
+    let a_taken_or_lower = server_key.boolean_bitor(&a.taken, &higher);
+    let b_wins = server_key.boolean_bitand(&b_available, &a_taken_or_lower);
+
+    let ((score, index), (decision, taken)) = rayon::join(
+        || rayon::join(
+            || server_key.if_then_else_parallelized(&b_wins, &b.score, &a.score),
+            || server_key.if_then_else_parallelized(&b_wins, &b.index, &a.index),
+        ),
+        || rayon::join(
+            || server_key.if_then_else_parallelized(&b_wins, &b.decision, &a.decision),
+            || select_boolean(&b_wins, &b.taken, &a.taken, server_key),
+        ),
+    );
+    Candidate { score, index, decision, taken }
+}
+
+fn select_boolean(condition: &BooleanBlock, if_true: &BooleanBlock, if_false: &BooleanBlock, server_key: &ServerKey) -> BooleanBlock {
+    let not_condition = server_key.boolean_bitnot(condition);
+    let from_true = server_key.boolean_bitand(condition, if_true);
+    let from_false = server_key.boolean_bitand(&not_condition, if_false);
+    server_key.boolean_bitor(&from_true, &from_false)
+}
@@ -0,0 +1,170 @@
+use itertools::{izip, Itertools};
+use serde::Serialize;
+use crate::common::ciphertexts::Decision;
+use crate::common::error::{Error, Result};
+
+// Cleartext run of the encrypted pipeline, keeping the value of every stage
+#[derive(Clone, Debug, Serialize)]
+pub struct ReferenceTrace {
+    pub pbs_outputs: Vec<u64>,
+    pub placed_blocks: Vec<u64>,
+    pub block_sums: Vec<u64>,
+    pub sum: Vec<u64>,
+    pub score: u64,
+    pub reject_threshold: u64,
+    pub accept_threshold: u64,
+    pub decision: Decision,
+}
+
+pub fn run_reference(
+    luts: &[Vec<Vec<u64>>],
+    repeated_probes: &[u64],
+    output_indices: &[u64],
+    sum_block_len: usize,
+    decomp_base: u64,
+    (reject_threshold, accept_threshold): (u64, u64),
+) -> Result<ReferenceTrace> {
+    let num_cts = luts.len();
+    let flat_luts = luts.iter().flatten().collect_vec();
+    if flat_luts.len() != repeated_probes.len() {
+        return Err(Error::shape("probe blocks", flat_luts.len(), repeated_probes.len()));
+    }
+    if flat_luts.len() != output_indices.len() {
+        return Err(Error::shape("lut output indices", flat_luts.len(), output_indices.len()));
+    }
+
+    // PBS: every lut is evaluated on its copy of the probe value
+    let pbs_outputs = izip!(flat_luts.iter(), repeated_probes).map(|(lut, probe)| {
+        lut.get(*probe as usize).cloned().ok_or_else(|| Error::shape("lut entries", *probe as usize + 1, lut.len()))
+    }).collect::<Result<Vec<_>>>()?;
+
+    // Placement: the outputs are scattered into num_cts radix numbers of sum_block_len blocks
+    let mut placed_blocks = vec![0u64; num_cts * sum_block_len];
+    for (out_idx, v) in izip!(output_indices, pbs_outputs.iter()) {
+        let block = placed_blocks.get_mut(*out_idx as usize).ok_or_else(|| Error::shape("sum blocks", *out_idx as usize + 1, num_cts * sum_block_len))?;
+        *block = *v;
+    }
+
+    // Block sum: add the radix numbers block wise, then propagate the carries
+    let block_sums = (0..sum_block_len).map(|block| {
+        placed_blocks.iter().skip(block).step_by(sum_block_len).sum::<u64>()
+    }).collect_vec();
+
+    let mut carry = 0u64;
+    let sum = block_sums.iter().map(|block_sum| {
+        let v = block_sum + carry;
+        carry = v / decomp_base;
+        v % decomp_base
+    }).collect_vec();
+
+    let score = sum.iter().rev().fold(0u64, |acc, digit| acc * decomp_base + digit);
+    let decision = Decision::from_score(score, reject_threshold, accept_threshold);
+
+    Ok(ReferenceTrace {
+        pbs_outputs,
+        placed_blocks,
+        block_sums,
+        sum,
+        score,
+        reject_threshold,
+        accept_threshold,
+        decision,
+    })
+}
+
+impl ReferenceTrace {
+    // Returns a description of the first stage whose decrypted values differ from the reference
+    pub fn first_mismatch(&self, placed_blocks: &[u64], sum: &[u64], decision: &[u64]) -> Option<String> {
+        if placed_blocks.len() != self.placed_blocks.len() {
+            return Some(format!("PBS output: expected {} blocks, got {}", self.placed_blocks.len(), placed_blocks.len()));
+        }
+        if let Some(idx) = izip!(&self.placed_blocks, placed_blocks).position(|(a, b)| a != b) {
+            return Some(format!("PBS output block {}: expected {}, got {}", idx, self.placed_blocks[idx], placed_blocks[idx]));
+        }
+        if let Some(idx) = izip!(&self.sum, sum).position(|(a, b)| a != b) {
+            return Some(format!("Sum block {}: expected {:?}, got {:?}", idx, self.sum, sum));
+        }
+        if decision.first().and_then(|d| Decision::from_count(*d).ok()) != Some(self.decision) {
+            return Some(format!("Decision: expected {}, got {:?}", self.decision, decision));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::repeat_n;
+    use crate::common::data::{get_lut_output_indices, make_row_based_luts};
+
+    // Template bins (0, 1) of the tables [[5, 0], [0, 5]] and [[3, 1], [1, 3]] in base 4: the row 5, 0
+    // of the first table takes two digits, written to blocks 0 and 1, the row 1, 3 of the second one
+    // digit, written to block 0 of the second number
+    fn luts() -> Vec<Vec<Vec<u64>>> {
+        vec![vec![vec![1, 0], vec![1, 0]], vec![vec![1, 3]]]
+    }
+
+    #[test]
+    fn follows_a_hand_computed_run() {
+        // Probe bins (0, 1) score 5 + 3 = 8
+        let trace = run_reference(&luts(), &[0, 0, 1], &[0, 1, 2], 2, 4, (4, 8)).unwrap();
+        assert_eq!(trace.pbs_outputs, [1, 1, 3]);
+        assert_eq!(trace.placed_blocks, [1, 1, 3, 0]);
+        assert_eq!(trace.block_sums, [4, 1]);
+        assert_eq!(trace.sum, [0, 2]);
+        assert_eq!(trace.score, 8);
+        assert_eq!(trace.decision, Decision::Accept);
+
+        // Probe bins (1, 0) score 0 + 1 = 1
+        let trace = run_reference(&luts(), &[1, 1, 0], &[0, 1, 2], 2, 4, (4, 8)).unwrap();
+        assert_eq!(trace.sum, [1, 0]);
+        assert_eq!(trace.score, 1);
+        assert_eq!(trace.decision, Decision::Reject);
+    }
+
+    #[test]
+    fn decides_on_the_thresholds() {
+        let decide = |thresholds| run_reference(&luts(), &[0, 0, 1], &[0, 1, 2], 2, 4, thresholds).unwrap().decision;
+        assert_eq!(decide((8, 8)), Decision::Accept);
+        assert_eq!(decide((4, 9)), Decision::StepUp);
+        assert_eq!(decide((9, 10)), Decision::Reject);
+        assert_eq!(decide((0, 0)), Decision::Accept);
+    }
+
+    #[test]
+    fn scores_every_pair_of_a_table() {
+        let tables = vec![vec![vec![5, 0, 2], vec![0, 5, 1], vec![2, 1, 6]], vec![vec![3, 1, 0], vec![1, 3, 1], vec![0, 1, 3]]];
+        let (output_indices, decomp_lengths, sum_block_len) = get_lut_output_indices(&tables, 4).unwrap();
+        assert_eq!(decomp_lengths, [2, 1]);
+        assert_eq!(sum_block_len, 2);
+        for template in (0..3).cartesian_product(0..3) {
+            let luts = make_row_based_luts(&tables, &[template.0, template.1], 4).unwrap();
+            for probe in (0..3u64).cartesian_product(0..3u64) {
+                let repeated = repeat_n(probe.0, decomp_lengths[0]).chain(repeat_n(probe.1, decomp_lengths[1])).collect_vec();
+                let trace = run_reference(&luts, &repeated, &output_indices, sum_block_len, 4, (4, 8)).unwrap();
+                let expected = tables[0][template.0 as usize][probe.0 as usize] + tables[1][template.1 as usize][probe.1 as usize];
+                assert_eq!(trace.score, expected, "template {:?}, probe {:?}", template, probe);
+                assert_eq!(trace.decision, Decision::from_score(expected, 4, 8));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_layouts() {
+        assert!(run_reference(&luts(), &[0, 0], &[0, 1, 2], 2, 4, (4, 8)).is_err());
+        assert!(run_reference(&luts(), &[0, 0, 1], &[0, 1], 2, 4, (4, 8)).is_err());
+        assert!(run_reference(&luts(), &[0, 0, 2], &[0, 1, 2], 2, 4, (4, 8)).is_err());
+        assert!(run_reference(&luts(), &[0, 0, 1], &[0, 1, 4], 2, 4, (4, 8)).is_err());
+    }
+
+    #[test]
+    fn reports_the_first_mismatch() {
+        let trace = run_reference(&luts(), &[0, 0, 1], &[0, 1, 2], 2, 4, (4, 8)).unwrap();
+        assert_eq!(trace.first_mismatch(&[1, 1, 3, 0], &[0, 2], &[2]), None);
+        assert!(trace.first_mismatch(&[1, 1, 3], &[0, 2], &[2]).unwrap().contains("expected 4 blocks"));
+        assert!(trace.first_mismatch(&[1, 1, 2, 0], &[0, 2], &[2]).unwrap().contains("block 2"));
+        assert!(trace.first_mismatch(&[1, 1, 3, 0], &[0, 1], &[2]).unwrap().starts_with("Sum block 1"));
+        assert!(trace.first_mismatch(&[1, 1, 3, 0], &[0, 2], &[1]).unwrap().starts_with("Decision"));
+        assert!(trace.first_mismatch(&[1, 1, 3, 0], &[0, 2], &[3]).unwrap().starts_with("Decision"));
+    }
+}
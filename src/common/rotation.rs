@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use crate::common::ciphertexts::EncryptedTemplate;
+use crate::common::error::{Error, Result};
+use crate::common::keys::ClientKey;
+use crate::common::store::TemplateStore;
+
+// Name of the progress file kept in the target store, one migrated subject id per line
+pub const PROGRESS_FILE: &str = "rotation.progress";
+
+#[derive(Debug, Default)]
+pub struct RotationReport {
+    pub migrated: Vec<String>,
+    // Subjects migrated by an earlier, interrupted run
+    pub already_migrated: Vec<String>,
+    // Templates of other datasets, they are encrypted under other keys
+    pub skipped: Vec<String>,
+}
+
+// Plaintext content of a template: the rounded LUT accumulators and the threshold digits
+fn template_plaintexts(key: &ClientKey, template: &EncryptedTemplate) -> (Vec<u64>, Vec<u64>, Vec<u64>) {
+    (
+        key.decrypt_luts(&template.luts),
+        key.decrypt_list(template.reject_threshold.as_view()),
+        key.decrypt_list(template.accept_threshold.as_view()),
+    )
+}
+
+// Decrypts the LUTs and thresholds of `template` with the old key and encrypts them again under the
+// new one. Both keys are in memory at once, so this has to run in the trusted client environment.
+pub fn reencrypt_template(old_key: &ClientKey, new_key: &mut ClientKey, template: &EncryptedTemplate) -> Result<EncryptedTemplate> {
+    template.validate()?;
+    if old_key.params() != template.params()? || new_key.params() != template.params()? {
+        return Err(Error::ParameterMismatch(format!("template of {}", template.dataset)));
+    }
+    let (luts, reject_threshold, accept_threshold) = template_plaintexts(old_key, template);
+    Ok(EncryptedTemplate {
+        luts: new_key.encrypt_lut_plaintexts(&luts),
+        reject_threshold: new_key.encrypt_radix_digits(&reject_threshold),
+        accept_threshold: new_key.encrypt_radix_digits(&accept_threshold),
+        ..template.clone()
+    })
+}
+
+// Checks that `migrated` decrypts under the new key to exactly what `original` decrypts to under
+// the old key
+pub fn verify_reencrypted(old_key: &ClientKey, new_key: &ClientKey, original: &EncryptedTemplate, migrated: &EncryptedTemplate) -> std::result::Result<(), String> {
+    migrated.validate().map_err(|err| err.to_string())?;
+    let (old_luts, old_reject, old_accept) = template_plaintexts(old_key, original);
+    let (new_luts, new_reject, new_accept) = template_plaintexts(new_key, migrated);
+    if old_luts.len() != new_luts.len() {
+        return Err(format!("{} LUT coefficients after re-encryption, expected {}", new_luts.len(), old_luts.len()));
+    }
+    if let Some(idx) = old_luts.iter().zip(&new_luts).position(|(a, b)| a != b) {
+        return Err(format!("LUT coefficient {} differs after re-encryption", idx));
+    }
+    if old_reject != new_reject || old_accept != new_accept {
+        return Err("thresholds differ after re-encryption".to_string());
+    }
+    Ok(())
+}
+
+fn read_progress(path: &str) -> Result<HashSet<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().filter(|line| !line.is_empty()).map(str::to_string).collect()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(err) => Err(Error::io(path, err)),
+    }
+}
+
+fn record_progress(path: &str, subject_id: &str) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| Error::io(path, err))?;
+    writeln!(file, "{}", subject_id).map_err(|err| Error::io(path, err))?;
+    file.sync_all().map_err(|err| Error::io(path, err))
+}
+
+// Moves every template of `dataset` in `source` to `target`, re-encrypted under `new_key`. The source store is
+// left untouched. A subject is recorded in the progress file of the target store only once its
+// migrated template was read back and verified, an interrupted run resumes after the last
+// recorded subject. The progress file is removed once every subject is migrated.
+pub fn rotate_store(dataset: &str, old_key: &ClientKey, new_key: &mut ClientKey, source: &TemplateStore, target: &TemplateStore) -> Result<RotationReport> {
+    let progress_path = target.dir().join(PROGRESS_FILE).to_string_lossy().to_string();
+    let done = read_progress(&progress_path)?;
+    let mut report = RotationReport::default();
+
+    for subject_id in source.list()? {
+        if done.contains(&subject_id) {
+            report.already_migrated.push(subject_id);
+            continue;
+        }
+        let original = source.get(&subject_id)?.ok_or_else(|| Error::UnknownSubject(subject_id.clone()))?;
+        if !original.dataset.eq_ignore_ascii_case(dataset) {
+            report.skipped.push(subject_id);
+            continue;
+        }
+        let migrated = reencrypt_template(old_key, new_key, &original)?;
+        target.insert(&subject_id, &migrated)?;
+
+        let stored = target.get(&subject_id)?.ok_or_else(|| Error::UnknownSubject(subject_id.clone()))?;
+        verify_reencrypted(old_key, new_key, &original, &stored)
+            .map_err(|reason| Error::Rotation { subject_id: subject_id.clone(), reason })?;
+        record_progress(&progress_path, &subject_id)?;
+        report.migrated.push(subject_id);
+    }
+    // A later rotation into the same store starts from scratch rather than skipping its subjects
+    match fs::remove_file(&progress_path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(Error::io(&progress_path, err)),
+        _ => Ok(report),
+    }
+}
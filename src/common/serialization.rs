@@ -0,0 +1,187 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::{Error, Result};
+
+// Magic and format version of one kind of file. Every kind has its own version, which is only
+// bumped when its payload changes, so e.g. a new template layout leaves saved keys loadable.
+#[derive(Clone, Copy)]
+pub struct FileKind {
+    pub magic: [u8; 4],
+    pub version: u32,
+}
+
+// Every file starts with a magic identifying its content, the format version and the parameter
+// set, followed by the bincode payload and a SHA-256 checksum over parameters and payload.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    magic: [u8; 4],
+    version: u32,
+    params: MultiBitPBSParameters,
+    payload: Vec<u8>,
+    checksum: [u8; 32],
+}
+
+fn checksum(params: &MultiBitPBSParameters, payload: &[u8]) -> Result<[u8; 32]> {
+    let params_bytes = bincode::serialize(params).map_err(|err| Error::format("parameters", &err.to_string()))?;
+    let mut hasher = Sha256::new();
+    hasher.update(&params_bytes);
+    hasher.update(payload);
+    Ok(hasher.finalize().into())
+}
+
+pub fn to_bytes<T: Serialize>(kind: FileKind, params: MultiBitPBSParameters, value: &T) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(value).map_err(|err| Error::format("payload", &err.to_string()))?;
+    let envelope = Envelope {
+        magic: kind.magic,
+        version: kind.version,
+        params,
+        checksum: checksum(&params, &payload)?,
+        payload,
+    };
+    bincode::serialize(&envelope).map_err(|err| Error::format("envelope", &err.to_string()))
+}
+
+pub fn from_bytes<T: DeserializeOwned>(source: &str, kind: FileKind, expected_params: Option<MultiBitPBSParameters>, bytes: &[u8]) -> Result<(MultiBitPBSParameters, T)> {
+    let envelope: Envelope = bincode::deserialize(bytes).map_err(|err| Error::format(source, &err.to_string()))?;
+    if envelope.magic != kind.magic {
+        return Err(Error::format(source, &format!("expected magic {:?}, found {:?}", kind.magic, envelope.magic)));
+    }
+    if envelope.version != kind.version {
+        return Err(Error::format(source, &format!("unsupported format version {}, expected {}", envelope.version, kind.version)));
+    }
+    if checksum(&envelope.params, &envelope.payload)? != envelope.checksum {
+        return Err(Error::format(source, "checksum mismatch"));
+    }
+    if let Some(expected) = expected_params {
+        if envelope.params != expected {
+            return Err(Error::ParameterMismatch(source.to_string()));
+        }
+    }
+    let value = bincode::deserialize(&envelope.payload).map_err(|err| Error::format(source, &err.to_string()))?;
+    Ok((envelope.params, value))
+}
+
+pub fn save<T: Serialize>(path: &str, kind: FileKind, params: MultiBitPBSParameters, value: &T) -> Result<()> {
+    let bytes = to_bytes(kind, params, value)?;
+    fs::write(path, bytes).map_err(|err| Error::io(path, err))
+}
+
+// Like save, for files holding secret material: the file is created readable and writable by the
+// owner only (mode 0600 on Unix). An existing file is never replaced, removing it is up to the caller.
+pub fn save_private<T: Serialize>(path: &str, kind: FileKind, params: MultiBitPBSParameters, value: &T) -> Result<()> {
+    let bytes = to_bytes(kind, params, value)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|err| Error::io(path, err))?;
+    file.write_all(&bytes).map_err(|err| Error::io(path, err))?;
+    file.sync_all().map_err(|err| Error::io(path, err))
+}
+
+pub fn load<T: DeserializeOwned>(path: &str, kind: FileKind, expected_params: Option<MultiBitPBSParameters>) -> Result<(MultiBitPBSParameters, T)> {
+    let bytes = fs::read(path).map_err(|err| Error::io(path, err))?;
+    from_bytes(path, kind, expected_params, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tfhe_utils::get_params_multi_bit_gpu;
+
+    const KIND: FileKind = FileKind { magic: *b"TEST", version: 2 };
+
+    fn params() -> MultiBitPBSParameters {
+        get_params_multi_bit_gpu(4).unwrap()
+    }
+
+    fn value() -> Vec<u64> {
+        vec![1, 2, 3, 5, 8]
+    }
+
+    fn format_reason(result: Result<(MultiBitPBSParameters, Vec<u64>)>) -> String {
+        match result {
+            Err(Error::Format { reason, .. }) => reason,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("bytes were accepted"),
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("serialization_{}_{}", name, std::process::id())).to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = to_bytes(KIND, params(), &value()).unwrap();
+        let (found_params, found): (_, Vec<u64>) = from_bytes("test", KIND, Some(params()), &bytes).unwrap();
+        assert_eq!(found_params, params());
+        assert_eq!(found, value());
+        // Without expected parameters the file's own are returned
+        assert_eq!(from_bytes::<Vec<u64>>("test", KIND, None, &bytes).unwrap().0, params());
+    }
+
+    #[test]
+    fn rejects_another_magic() {
+        let bytes = to_bytes(FileKind { magic: *b"ELSE", ..KIND }, params(), &value()).unwrap();
+        assert!(format_reason(from_bytes("test", KIND, None, &bytes)).contains("expected magic"));
+    }
+
+    #[test]
+    fn rejects_another_version() {
+        let bytes = to_bytes(FileKind { version: 1, ..KIND }, params(), &value()).unwrap();
+        assert_eq!(format_reason(from_bytes("test", KIND, None, &bytes)), "unsupported format version 1, expected 2");
+    }
+
+    #[test]
+    fn rejects_other_parameters() {
+        let bytes = to_bytes(KIND, get_params_multi_bit_gpu(8).unwrap(), &value()).unwrap();
+        assert!(matches!(from_bytes::<Vec<u64>>("test", KIND, Some(params()), &bytes), Err(Error::ParameterMismatch(_))));
+    }
+
+    #[test]
+    fn rejects_corrupted_bytes() {
+        let bytes = to_bytes(KIND, params(), &value()).unwrap();
+        // The payload ends right before the 32 byte checksum
+        let payload_end = bytes.len() - 32;
+        for position in [payload_end - 8, payload_end - 1, payload_end, bytes.len() - 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[position] ^= 0x10;
+            assert_eq!(format_reason(from_bytes("test", KIND, None, &corrupted)), "checksum mismatch", "byte {}", position);
+        }
+        assert!(from_bytes::<Vec<u64>>("test", KIND, None, &bytes[..bytes.len() - 1]).is_err());
+        assert!(from_bytes::<Vec<u64>>("test", KIND, None, &[]).is_err());
+    }
+
+    #[test]
+    fn saves_and_loads_files() {
+        let path = temp_path("save");
+        save(&path, KIND, params(), &value()).unwrap();
+        assert_eq!(load::<Vec<u64>>(&path, KIND, Some(params())).unwrap().1, value());
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(load::<Vec<u64>>(&path, KIND, None), Err(Error::Io { .. })));
+    }
+
+    #[test]
+    fn private_files_are_never_replaced() {
+        let path = temp_path("private");
+        save_private(&path, KIND, params(), &value()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(matches!(save_private(&path, KIND, params(), &vec![0u64]), Err(Error::Io { .. })));
+        assert_eq!(load::<Vec<u64>>(&path, KIND, None).unwrap().1, value());
+        fs::remove_file(&path).unwrap();
+    }
+}
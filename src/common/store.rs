@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::common::ciphertexts::EncryptedTemplate;
+use crate::common::error::{Error, Result};
+
+const TEMPLATE_EXTENSION: &str = "tpl";
+
+// Encrypted templates on disk, one file per subject in a single directory
+pub struct TemplateStore {
+    dir: PathBuf,
+}
+
+impl TemplateStore {
+    pub fn open(dir: &str) -> Result<Self> {
+        fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+        Ok(TemplateStore { dir: PathBuf::from(dir) })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn insert(&self, subject_id: &str, template: &EncryptedTemplate) -> Result<()> {
+        let path = self.path(subject_id)?;
+        let path_str = path.to_string_lossy().to_string();
+        // Write to a temporary file first so an interrupted insert never leaves a truncated template
+        let tmp_path = path.with_extension("tmp");
+        let tmp_path_str = tmp_path.to_string_lossy().to_string();
+        fs::write(&tmp_path, template.to_bytes()?).map_err(|err| Error::io(&tmp_path_str, err))?;
+        fs::rename(&tmp_path, &path).map_err(|err| Error::io(&path_str, err))
+    }
+
+    pub fn get(&self, subject_id: &str) -> Result<Option<EncryptedTemplate>> {
+        let path = self.path(subject_id)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        EncryptedTemplate::load(&path.to_string_lossy()).map(Some)
+    }
+
+    pub fn contains(&self, subject_id: &str) -> Result<bool> {
+        Ok(self.path(subject_id)?.exists())
+    }
+
+    // Returns whether a template was stored for the subject
+    pub fn remove(&self, subject_id: &str) -> Result<bool> {
+        let path = self.path(subject_id)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(&path).map_err(|err| Error::io(&path.to_string_lossy(), err))?;
+        Ok(true)
+    }
+
+    // Subject ids in sorted order
+    pub fn list(&self) -> Result<Vec<String>> {
+        let dir_str = self.dir.to_string_lossy().to_string();
+        let entries = fs::read_dir(&self.dir).map_err(|err| Error::io(&dir_str, err))?;
+        let mut subjects = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|err| Error::io(&dir_str, err))?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(TEMPLATE_EXTENSION) {
+                continue;
+            }
+            if let Some(subject_id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                subjects.push(subject_id.to_string());
+            }
+        }
+        subjects.sort();
+        Ok(subjects)
+    }
+
+    fn path(&self, subject_id: &str) -> Result<PathBuf> {
+        validate_subject_id(subject_id)?;
+        Ok(self.dir.join(format!("{}.{}", subject_id, TEMPLATE_EXTENSION)))
+    }
+}
+
+// Subject ids become file names, so only a conservative character set is accepted
+pub fn validate_subject_id(subject_id: &str) -> Result<()> {
+    let valid_chars = subject_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if subject_id.is_empty() || subject_id.starts_with('.') || !valid_chars {
+        return Err(Error::InvalidSubject(subject_id.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ciphertexts::tests::template;
+    use crate::common::tfhe_utils::get_params_multi_bit_gpu;
+
+    fn store_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("store_{}_{}", name, std::process::id())).to_string_lossy().to_string();
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn sample_template(dataset: &str) -> EncryptedTemplate {
+        let params = get_params_multi_bit_gpu(8).unwrap();
+        let mut template = template(params.glwe_dimension.to_glwe_size(), params.polynomial_size);
+        template.dataset = dataset.to_string();
+        template
+    }
+
+    #[test]
+    fn inserts_gets_and_lists() {
+        let dir = store_dir("insert");
+        let store = TemplateStore::open(&dir).unwrap();
+        assert_eq!(store.list().unwrap(), Vec::<String>::new());
+        assert!(store.get("alice").unwrap().is_none());
+
+        store.insert("bob", &sample_template("PUT")).unwrap();
+        store.insert("alice", &sample_template("PUT")).unwrap();
+        assert_eq!(store.list().unwrap(), ["alice", "bob"]);
+        assert!(store.contains("alice").unwrap());
+        let stored = store.get("alice").unwrap().unwrap();
+        assert_eq!(stored.to_bytes().unwrap(), sample_template("PUT").to_bytes().unwrap());
+
+        assert!(store.remove("alice").unwrap());
+        assert!(!store.remove("alice").unwrap());
+        assert_eq!(store.list().unwrap(), ["bob"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn persists_across_reopening() {
+        let dir = store_dir("persist");
+        TemplateStore::open(&dir).unwrap().insert("alice", &sample_template("PUT")).unwrap();
+        let reopened = TemplateStore::open(&dir).unwrap();
+        assert_eq!(reopened.list().unwrap(), ["alice"]);
+        assert_eq!(reopened.get("alice").unwrap().unwrap().dataset, "PUT");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_subject_replaces_the_template() {
+        let dir = store_dir("duplicate");
+        let store = TemplateStore::open(&dir).unwrap();
+        store.insert("alice", &sample_template("PUT")).unwrap();
+        store.insert("alice", &sample_template("FRGC")).unwrap();
+        assert_eq!(store.list().unwrap(), ["alice"]);
+        assert_eq!(store.get("alice").unwrap().unwrap().dataset, "FRGC");
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_foreign_files_and_reports_broken_ones() {
+        let dir = store_dir("files");
+        let store = TemplateStore::open(&dir).unwrap();
+        fs::write(Path::new(&dir).join("notes.txt"), "not a template").unwrap();
+        fs::write(Path::new(&dir).join("carol.tmp"), "interrupted insert").unwrap();
+        fs::write(Path::new(&dir).join("dave.tpl"), "garbage").unwrap();
+        assert_eq!(store.list().unwrap(), ["dave"]);
+        assert!(matches!(store.get("dave"), Err(Error::Format { .. })));
+        assert!(store.get("carol").unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory_is_created_and_vanished_one_reported() {
+        let dir = store_dir("missing");
+        let store = TemplateStore::open(&format!("{}/nested", dir)).unwrap();
+        assert!(store.dir().is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(store.list(), Err(Error::Io { .. })));
+        assert!(store.get("alice").unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_the_case_of_subject_ids() {
+        let dir = store_dir("case");
+        let store = TemplateStore::open(&dir).unwrap();
+        store.insert("Alice", &sample_template("PUT")).unwrap();
+        assert_eq!(store.list().unwrap(), ["Alice"]);
+        assert!(store.contains("Alice").unwrap());
+        // Ids are case sensitive wherever the file system is
+        #[cfg(target_os = "linux")]
+        assert!(!store.contains("alice").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_ids_that_are_not_file_names() {
+        let store = TemplateStore::open(&store_dir("ids")).unwrap();
+        for subject_id in ["", ".hidden", "../escape", "a/b", "a b", "é"] {
+            assert!(matches!(store.insert(subject_id, &sample_template("PUT")), Err(Error::InvalidSubject(_))), "{:?}", subject_id);
+            assert!(matches!(store.get(subject_id), Err(Error::InvalidSubject(_))));
+        }
+        assert!(validate_subject_id("subject-01_a.b").is_ok());
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+}
@@ -1,21 +1,29 @@
 use itertools::Itertools;
 use tfhe::core_crypto::entities::LweCiphertextListOwned;
+#[cfg(feature = "gpu")]
 use tfhe::core_crypto::gpu::glwe_ciphertext_list::CudaGlweCiphertextList;
+#[cfg(feature = "gpu")]
 use tfhe::core_crypto::gpu::lwe_ciphertext_list::CudaLweCiphertextList;
+#[cfg(feature = "gpu")]
 use tfhe::core_crypto::gpu::CudaStreams;
 use tfhe::core_crypto::prelude::*;
-use tfhe::shortint::parameters::{PARAM_GPU_MULTI_BIT_GROUP_3_MESSAGE_2_CARRY_2_KS_PBS_GAUSSIAN_2M64, PARAM_GPU_MULTI_BIT_GROUP_3_MESSAGE_3_CARRY_3_KS_PBS_GAUSSIAN_2M64};
-use tfhe::shortint::{CarryModulus, MessageModulus, MultiBitPBSParameters, ShortintParameterSet};
+use tfhe::shortint::parameters::{PARAM_GPU_MULTI_BIT_MESSAGE_2_CARRY_2_GROUP_3_KS_PBS, PARAM_GPU_MULTI_BIT_MESSAGE_3_CARRY_3_GROUP_3_KS_PBS};
+use tfhe::shortint::{CarryModulus, MessageModulus, MultiBitPBSParameters, PBSParameters, ShortintParameterSet};
+use crate::common::error::{Error, Result};
 
-pub fn get_params_multi_bit_gpu(total_bits: u64) -> MultiBitPBSParameters {
+pub fn get_params_multi_bit_gpu(total_bits: u64) -> Result<MultiBitPBSParameters> {
     match total_bits {
-        4 => PARAM_GPU_MULTI_BIT_GROUP_3_MESSAGE_2_CARRY_2_KS_PBS_GAUSSIAN_2M64,
-        8 => PARAM_GPU_MULTI_BIT_GROUP_3_MESSAGE_3_CARRY_3_KS_PBS_GAUSSIAN_2M64,
-        _ => panic!("Not implemented for {} bits", total_bits)
+        4 => Ok(PARAM_GPU_MULTI_BIT_MESSAGE_2_CARRY_2_GROUP_3_KS_PBS),
+        8 => Ok(PARAM_GPU_MULTI_BIT_MESSAGE_3_CARRY_3_GROUP_3_KS_PBS),
+        _ => Err(Error::UnsupportedParameters(format!("Not implemented for {} bits", total_bits)))
     }
 }
 
-pub fn encode_encrypt_list(values: &[u64], delta: u64, sk: LweSecretKeyView<u64>, noise_distribution: DynamicDistribution<u64>, mut encryption_generator: &mut EncryptionRandomGenerator<ActivatedRandomGenerator>, params: &ShortintParameterSet) -> LweCiphertextListOwned<u64> {
+pub fn short_params(params: MultiBitPBSParameters) -> ShortintParameterSet {
+    ShortintParameterSet::new_pbs_param_set(PBSParameters::MultiBitPBS(params))
+}
+
+pub fn encode_encrypt_list(values: &[u64], delta: u64, sk: LweSecretKeyView<u64>, noise_distribution: DynamicDistribution<u64>, encryption_generator: &mut EncryptionRandomGenerator<ActivatedRandomGenerator>, params: &ShortintParameterSet) -> LweCiphertextListOwned<u64> {
     let msg_count = values.len();
     let pt_count = PlaintextCount(msg_count);
     let ct_count = LweCiphertextCount(msg_count);
@@ -37,12 +45,13 @@ pub fn encode_encrypt_list(values: &[u64], delta: u64, sk: LweSecretKeyView<u64>
         &mut output,
         &pt_list,
         noise_distribution,
-        &mut encryption_generator,
+        encryption_generator,
     );
 
     output
 }
 
+#[cfg(feature = "gpu")]
 pub fn encode_encrypt_list_cuda(values: &[u64], delta: u64, sk: LweSecretKeyView<u64>, noise_distribution: DynamicDistribution<u64>, encryption_generator: &mut EncryptionRandomGenerator<ActivatedRandomGenerator>, params: &ShortintParameterSet, streams: &CudaStreams) -> CudaLweCiphertextList<u64> {
     let output = encode_encrypt_list(values, delta, sk, noise_distribution, encryption_generator, params);
     CudaLweCiphertextList::from_lwe_ciphertext_list(&output, streams)
@@ -65,6 +74,7 @@ pub fn decrypt_decode_list(input_cts: LweCiphertextListView<u64>, delta: u64, sk
     }).collect_vec()
 }
 
+#[cfg(feature = "gpu")]
 pub fn decrypt_decode_list_cuda(input_cts: &CudaLweCiphertextList<u64>, delta: u64, sk: LweSecretKeyView<u64>, streams: &CudaStreams) -> Vec<u64> {
     let lwe_cts = input_cts.to_lwe_ciphertext_list(&streams);
     decrypt_decode_list(lwe_cts.as_view(), delta, sk)
@@ -79,11 +89,39 @@ pub fn new_ct_list(ct_count: usize, lwe_size: LweSize, params: &ShortintParamete
     )
 }
 
+#[cfg(feature = "gpu")]
 pub fn new_ct_list_cuda(ct_count: usize, lwe_size: LweSize, params: &ShortintParameterSet, streams: &CudaStreams) -> CudaLweCiphertextList<u64> {
     let output = new_ct_list(ct_count, lwe_size, params);
     CudaLweCiphertextList::from_lwe_ciphertext_list(&output, streams)
 }
 
+// `times` copies of `list` one after the other
+pub fn repeat_lwe_list(list: &LweCiphertextListOwned<u64>, times: usize) -> LweCiphertextListOwned<u64> {
+    LweCiphertextList::from_container(
+        list.as_ref().repeat(times),
+        list.lwe_size(),
+        list.ciphertext_modulus(),
+    )
+}
+
+// Lists of ciphertexts of the same size, one after the other
+pub fn concat_glwe_lists(lists: &[&GlweCiphertextListOwned<u64>]) -> Result<GlweCiphertextListOwned<u64>> {
+    let first = lists.first().ok_or_else(|| Error::shape("GLWE lists to concatenate", 1, 0))?;
+    Ok(GlweCiphertextList::from_container(
+        lists.iter().flat_map(|list| list.as_ref().iter().copied()).collect_vec(),
+        first.glwe_size(),
+        first.polynomial_size(),
+        first.ciphertext_modulus(),
+    ))
+}
+
+// Consecutive lists of `chunk_len` ciphertexts, the last one may be shorter
+pub fn split_lwe_list(list: &LweCiphertextListOwned<u64>, chunk_len: usize) -> Vec<LweCiphertextListOwned<u64>> {
+    list.chunks(chunk_len)
+        .map(|chunk| LweCiphertextList::from_container(chunk.as_ref().to_vec(), chunk.lwe_size(), chunk.ciphertext_modulus()))
+        .collect_vec()
+}
+
 pub fn fill_accumulator<F, C>(
     accumulator: &mut GlweCiphertext<C>,
     polynomial_size: PolynomialSize,
@@ -169,6 +207,7 @@ where
     glwe_list
 }
 
+#[cfg(feature = "gpu")]
 pub fn make_accumulator_list_cuda<F>(fs: &[F], params: &ShortintParameterSet, streams: &CudaStreams) -> CudaGlweCiphertextList<u64>
 where
     F: Fn(u64) -> u64,
@@ -177,6 +216,7 @@ where
     CudaGlweCiphertextList::from_glwe_ciphertext_list(&glwe_list, streams)
 }
 
+#[cfg(feature = "gpu")]
 pub fn make_encrypted_accumulator_list_cuda<F>(
     fs: &[F],
     params: &ShortintParameterSet,
@@ -192,15 +232,42 @@ where
     CudaGlweCiphertextList::from_glwe_ciphertext_list(&glwe_list, streams)
 }
 
+// Plaintext polynomials of a GLWE list, every coefficient rounded to the closest multiple of delta.
+// Applied to encrypted accumulators, this recovers the accumulators exactly.
+pub fn decrypt_round_glwe_list(list: &GlweCiphertextListOwned<u64>, delta: u64, sk: GlweSecretKeyView<u64>) -> Vec<u64> {
+    let mut pt_list = PlaintextList::new(0, PlaintextCount(list.glwe_ciphertext_count().0 * list.polynomial_size().0));
+    decrypt_glwe_ciphertext_list(&sk, list, &mut pt_list);
+    pt_list.iter()
+        .map(|pt| ((*pt.0).wrapping_add(delta >> 1) / delta).wrapping_mul(delta))
+        .collect_vec()
+}
+
+// Encrypts plaintext polynomials, one GLWE ciphertext per `polynomial_size` coefficients
+pub fn encrypt_glwe_plaintexts(
+    plaintexts: &[u64],
+    params: &ShortintParameterSet,
+    glwe_secret_key: GlweSecretKeyView<u64>,
+    noise: DynamicDistribution<u64>,
+    generator: &mut EncryptionRandomGenerator<ActivatedRandomGenerator>,
+) -> GlweCiphertextListOwned<u64> {
+    let count = plaintexts.len() / params.polynomial_size().0;
+    let mut glwe_list = GlweCiphertextList::new(0, params.glwe_dimension().to_glwe_size(), params.polynomial_size(), GlweCiphertextCount(count), params.ciphertext_modulus());
+    glwe_list.iter_mut().zip(plaintexts.chunks(params.polynomial_size().0)).for_each(|(mut ct, polynomial)| {
+        ct.get_mut_body().as_mut().copy_from_slice(polynomial);
+        encrypt_glwe(&glwe_secret_key, &mut ct, noise, generator);
+    });
+    glwe_list
+}
+
 pub fn encrypt_glwe(
     glwe_secret_key: &GlweSecretKeyView<u64>,
-    mut glwe_ct: &mut GlweCiphertextMutView<u64>,
+    glwe_ct: &mut GlweCiphertextMutView<u64>,
     noise: DynamicDistribution<u64>,
     generator: &mut EncryptionRandomGenerator<ActivatedRandomGenerator>,
 ) {
     encrypt_glwe_ciphertext_assign(
-        &glwe_secret_key,
-        &mut glwe_ct,
+        glwe_secret_key,
+        glwe_ct,
         noise,
         generator,
     )
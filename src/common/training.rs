@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use itertools::Itertools;
+use rayon::prelude::*;
+use crate::common::error::{Error, Result};
+use crate::common::qbins::{check_bin_count, generate_qbins, write_qbins, BinSource};
+
+// Intervals of the Simpson rule per quantization bin
+const INTEGRATION_STEPS: usize = 256;
+// The outer bins are integrated up to this many standard deviations
+const TAIL_BOUND: f64 = 8.0;
+// Correlations above this make the mated distribution degenerate
+const MAX_CORRELATION: f64 = 0.99;
+
+// Settings of the HELR table training, the bins are generated as by the qbins module
+#[derive(Clone, Debug)]
+pub struct TrainingOptions {
+    // Number of equiprobable quantization bins, the dimension of every table
+    pub num_bins: usize,
+    pub source: BinSource,
+    pub per_feature: bool,
+    // Factor applied to the natural log-likelihood ratios before rounding, 10 keeps a resolution of
+    // a tenth of a nat, close to the bundled tables
+    pub scale: f64,
+}
+
+impl Default for TrainingOptions {
+    fn default() -> Self {
+        TrainingOptions { num_bins: 64, source: BinSource::Gaussian, per_feature: false, scale: 10.0 }
+    }
+}
+
+// Gaussian model of one feature after standardization: subject means with variance `between`
+// and samples around them with variance `within`, `between + within = 1`
+#[derive(Clone, Copy, Debug)]
+pub struct FeatureModel {
+    pub mean: f64,
+    pub std_dev: f64,
+    pub within: f64,
+    pub between: f64,
+}
+
+impl FeatureModel {
+    // Correlation of two standardized samples of the same subject
+    fn correlation(&self) -> f64 {
+        self.between.clamp(0.0, MAX_CORRELATION)
+    }
+}
+
+pub struct TrainedTables {
+    pub models: Vec<FeatureModel>,
+    // Bin boundaries, one row shared by all features or one row per feature
+    pub qbins: Vec<Vec<f64>>,
+    pub tables: Vec<Vec<Vec<i64>>>,
+}
+
+// Estimates the within-class and between-class variance of every feature. The within-class
+// variance is pooled over all subjects with at least two samples.
+pub fn estimate_models(labels: &[String], features: &[Vec<f64>]) -> Result<Vec<FeatureModel>> {
+    let dimension = features.first().ok_or_else(|| Error::shape("training vectors", 1, 0))?.len();
+    if let Some(vector) = features.iter().find(|vector| vector.len() != dimension) {
+        return Err(Error::shape("training vector features", dimension, vector.len()));
+    }
+    if labels.len() != features.len() {
+        return Err(Error::shape("training labels", features.len(), labels.len()));
+    }
+
+    let mut samples_by_label: HashMap<&str, Vec<usize>> = HashMap::new();
+    labels.iter().enumerate().for_each(|(idx, label)| samples_by_label.entry(label.as_str()).or_default().push(idx));
+    let classes = samples_by_label.values().filter(|samples| samples.len() > 1).collect_vec();
+    let within_dof: usize = classes.iter().map(|samples| samples.len() - 1).sum();
+    if within_dof == 0 {
+        return Err(Error::format("training vectors", "no subject has more than one sample, the within-class variance can not be estimated"));
+    }
+
+    (0..dimension).map(|feature| {
+        let values = features.iter().map(|vector| vector[feature]).collect_vec();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1).max(1) as f64;
+        if variance <= 0.0 {
+            return Err(Error::format("training vectors", &format!("feature {} is constant", feature)));
+        }
+        let within_sum: f64 = classes.iter().map(|samples| {
+            let class_mean = samples.iter().map(|&idx| values[idx]).sum::<f64>() / samples.len() as f64;
+            samples.iter().map(|&idx| (values[idx] - class_mean).powi(2)).sum::<f64>()
+        }).sum();
+        let within = (within_sum / within_dof as f64 / variance).min(1.0);
+        Ok(FeatureModel { mean, std_dev: variance.sqrt(), within, between: 1.0 - within })
+    }).collect()
+}
+
+// HELR table of one feature: entry (i, j) is the log-likelihood ratio of a template in bin i and a
+// probe in bin j coming from the same subject rather than from two different subjects, scaled and
+// rounded. `qbins` are the boundaries on the feature itself, the table has one row per bin. Fails
+// on a bin beyond TAIL_BOUND standard deviations, which has no probability mass and so no ratio.
+pub fn helr_table(model: &FeatureModel, qbins: &[f64], scale: f64) -> Result<Vec<Vec<i64>>> {
+    let standardized = qbins.iter().map(|bound| ((bound - model.mean) / model.std_dev).clamp(-TAIL_BOUND, TAIL_BOUND));
+    let bounds = std::iter::once(-TAIL_BOUND).chain(standardized).chain(std::iter::once(TAIL_BOUND)).collect_vec();
+    let marginals = bounds.iter().tuple_windows().map(|(lo, hi)| normal_interval(*lo, *hi)).collect_vec();
+    if let Some(bin) = marginals.iter().position(|p| *p < f64::MIN_POSITIVE) {
+        return Err(Error::format("quantization bins", &format!(
+            "bin {} lies more than {} standard deviations from the feature mean {:.4}", bin, TAIL_BOUND, model.mean)));
+    }
+    let rho = model.correlation();
+    let conditional_std = (1.0 - rho * rho).sqrt();
+
+    Ok(bounds.iter().tuple_windows().enumerate().map(|(i, (x_lo, x_hi))| {
+        bounds.iter().tuple_windows().enumerate().map(|(j, (y_lo, y_hi))| {
+            // P(template in bin i, probe in bin j | same subject)
+            let mated = simpson(*x_lo, *x_hi, |x| {
+                normal_pdf(x) * normal_interval((y_lo - rho * x) / conditional_std, (y_hi - rho * x) / conditional_std)
+            }).max(f64::MIN_POSITIVE);
+            let llr = (mated / (marginals[i] * marginals[j])).ln();
+            (llr * scale).round() as i64
+        }).collect()
+    }).collect())
+}
+
+// Trains one table per feature from labelled, not necessarily standardized, vectors
+pub fn train_helr_tables(labels: &[String], features: &[Vec<f64>], options: &TrainingOptions) -> Result<TrainedTables> {
+    let qbins = generate_qbins(features, options.num_bins, options.source, options.per_feature)?;
+    train_helr_tables_with_qbins(labels, features, qbins, options.scale)
+}
+
+// Like train_helr_tables with given bins. With one row per feature the rows may have different
+// lengths, the table of every feature then has the dimension of its own bins.
+pub fn train_helr_tables_with_qbins(labels: &[String], features: &[Vec<f64>], qbins: Vec<Vec<f64>>, scale: f64) -> Result<TrainedTables> {
+    let models = estimate_models(labels, features)?;
+    if qbins.len() != 1 && qbins.len() != models.len() {
+        return Err(Error::shape("qbins rows", models.len(), qbins.len()));
+    }
+    for row in &qbins {
+        check_bin_count(row.len() + 1)?;
+    }
+    let tables = models.par_iter().enumerate()
+        .map(|(idx, model)| helr_table(model, &qbins[idx.min(qbins.len() - 1)], scale))
+        .collect::<Result<_>>()?;
+    Ok(TrainedTables { models, qbins, tables })
+}
+
+// Writes `<dir>/<prefix><idx>.csv` per table, in the layout read by the data module, and the bin
+// boundaries to `<dir>/<name>_qbins.csv`
+pub fn write_tables(trained: &TrainedTables, dir: &str, prefix: &str, name: &str) -> Result<()> {
+    fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+    for (idx, table) in trained.tables.iter().enumerate() {
+        let path = Path::new(dir).join(format!("{}{}.csv", prefix, idx)).to_string_lossy().to_string();
+        let content = table.iter().map(|row| row.iter().join(",")).join("\n");
+        fs::write(&path, content + "\n").map_err(|err| Error::io(&path, err))?;
+    }
+    let qbins_path = Path::new(dir).join(format!("{}_qbins.csv", name)).to_string_lossy().to_string();
+    write_qbins(&qbins_path, &trained.qbins)
+}
+
+fn simpson<F: Fn(f64) -> f64>(lo: f64, hi: f64, f: F) -> f64 {
+    let step = (hi - lo) / INTEGRATION_STEPS as f64;
+    let inner: f64 = (1..INTEGRATION_STEPS).map(|k| {
+        let weight = if k % 2 == 1 { 4.0 } else { 2.0 };
+        weight * f(lo + k as f64 * step)
+    }).sum();
+    (f(lo) + inner + f(hi)) * step / 3.0
+}
+
+fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+// P(lo < X < hi) for a standard normal X. Right of the mean the difference of the upper tails is
+// taken, as both CDF values round to 1 there.
+fn normal_interval(lo: f64, hi: f64) -> f64 {
+    if lo > 0.0 {
+        normal_cdf(-lo) - normal_cdf(-hi)
+    } else {
+        normal_cdf(hi) - normal_cdf(lo)
+    }
+}
+
+// Complementary error function with a relative error below 1.2e-7 everywhere, also far in the tails
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418
+        + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587
+        + t * (-0.82215223 + t * 0.17087277))))))));
+    let result = t * poly.exp();
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(between: f64) -> FeatureModel {
+        FeatureModel { mean: 0.0, std_dev: 1.0, within: 1.0 - between, between }
+    }
+
+    #[test]
+    fn erfc_matches_known_values() {
+        let known = [(0.0, 1.0), (0.5, 0.4795001221869535), (1.0, 0.15729920705028513), (-1.0, 1.8427007929497148),
+            (2.0, 4.677734981047266e-3), (3.0, 2.209049699858544e-5), (5.0, 1.537_459_794_428_035e-12), (8.0, 1.1224297172982928e-29)];
+        for (x, expected) in known {
+            assert!(((erfc(x) - expected) / expected).abs() < 1.2e-7, "erfc({}) = {}, expected {}", x, erfc(x), expected);
+        }
+        assert!((normal_interval(-1.0, 1.0) - 0.6826894921370859).abs() < 1e-7);
+    }
+
+    #[test]
+    fn estimates_within_and_between_variance() {
+        // Subject means -1 and 1 and samples 0.5 around them: sample variance 5 / 3, pooled
+        // within-class variance 0.5 over 2 degrees of freedom
+        let labels = ["a", "a", "b", "b"].map(String::from);
+        let features = vec![vec![-1.5, 2.0], vec![-0.5, 2.0], vec![0.5, 4.0], vec![1.5, 4.0]];
+        let models = estimate_models(&labels, &features).unwrap();
+        assert!((models[0].mean).abs() < 1e-12);
+        assert!((models[0].std_dev - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert!((models[0].within - 0.5 / (5.0 / 3.0)).abs() < 1e-12);
+        assert!((models[0].between + models[0].within - 1.0).abs() < 1e-12);
+        assert!((models[1].mean - 3.0).abs() < 1e-12);
+        assert_eq!(models[1].within, 0.0);
+
+        let constant = vec![vec![-1.5, 2.0], vec![-0.5, 2.0], vec![0.5, 2.0], vec![1.5, 2.0]];
+        assert!(estimate_models(&labels, &constant).is_err());
+    }
+
+    #[test]
+    fn estimate_models_rejects_unusable_vectors() {
+        let labels = ["a", "b", "c"].map(String::from);
+        let features = vec![vec![0.0], vec![1.0], vec![2.0]];
+        assert!(estimate_models(&labels, &features).is_err());
+        assert!(estimate_models(&labels[..2], &features).is_err());
+        assert!(estimate_models(&labels, &[vec![0.0], vec![1.0, 2.0], vec![2.0]]).is_err());
+        assert!(estimate_models(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn helr_table_is_symmetric_and_favours_the_diagonal() {
+        let table = helr_table(&model(0.8), &[-1.0, 0.0, 1.0], 10.0).unwrap();
+        assert_eq!(table.len(), 4);
+        for (i, row) in table.iter().enumerate() {
+            assert_eq!(row.len(), 4);
+            for (j, value) in row.iter().enumerate() {
+                assert_eq!(*value, table[j][i]);
+                if i != j {
+                    assert!(row[i] > *value);
+                }
+            }
+        }
+        assert!(table[0][3] < 0 && table[0][0] > 0);
+    }
+
+    #[test]
+    fn helr_table_of_an_uninformative_feature_is_zero() {
+        let table = helr_table(&model(0.0), &[-0.5, 0.5], 10.0).unwrap();
+        assert!(table.iter().flatten().all(|v| *v == 0));
+    }
+
+    #[test]
+    fn helr_table_rejects_empty_bins() {
+        assert!(helr_table(&model(0.8), &[0.0, 9.0], 10.0).is_err());
+        assert!(helr_table(&model(0.8), &[-10.0, -9.0, 0.0], 10.0).is_err());
+        assert!(helr_table(&model(0.8), &[0.0, 7.0], 10.0).is_ok());
+    }
+}
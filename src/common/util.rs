@@ -3,7 +3,7 @@ use itertools::Itertools;
 pub fn decompose_to_base(mut value: u64, base: u64, blocks: usize) -> Vec<u64> {
     (0..blocks).map(|_| {
         let res = value % base;
-        value = value / base;
+        value /= base;
         res
     }).collect()
 }
@@ -27,6 +27,13 @@ pub fn flip_vectors<T>(vecs: Vec<Vec<T>>) -> Vec<Vec<T>> {
     new_vecs
 }
 
+pub fn arg_value(name: &str) -> Option<String> {
+    let args = std::env::args().collect::<Vec<_>>();
+    args.iter().position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+}
+
 //TESTING
 pub fn luts_to_closures(luts: Vec<Vec<u64>>) -> Vec<Box<dyn Fn(u64) -> u64>>
 {
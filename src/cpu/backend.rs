@@ -0,0 +1,91 @@
+use tfhe::core_crypto::prelude::*;
+use tfhe::shortint::{MultiBitPBSParameters, ShortintParameterSet};
+use crate::common::backend::{Backend, ComparisonType};
+use crate::common::error::Result;
+use crate::common::keys::ServerKey;
+use crate::common::radix::comparison_ct;
+use crate::common::ranking::top_k_lwe;
+use crate::common::tfhe_utils::split_lwe_list;
+use crate::cpu;
+use crate::common::keygen::host_server_keys;
+
+pub struct CpuBackend;
+
+pub struct CpuEvaluationKey {
+    pub fourier_bsk: FourierLweMultiBitBootstrapKeyOwned,
+    pub integer_key: tfhe::integer::ServerKey,
+}
+
+impl Backend for CpuBackend {
+    type LweList = LweCiphertextListOwned<u64>;
+    type GlweList = GlweCiphertextListOwned<u64>;
+    type EvaluationKey = CpuEvaluationKey;
+
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn evaluation_key(&self, server_key: &ServerKey) -> Self::EvaluationKey {
+        let (fourier_bsk, integer_key) = host_server_keys(&server_key.bootstrap_key, &server_key.key_switching_key, server_key.params);
+        CpuEvaluationKey { fourier_bsk, integer_key }
+    }
+
+    fn lwe_to_backend(&self, list: &LweCiphertextListOwned<u64>) -> Self::LweList {
+        list.clone()
+    }
+
+    fn glwe_to_backend(&self, list: &GlweCiphertextListOwned<u64>) -> Self::GlweList {
+        list.clone()
+    }
+
+    fn lwe_to_host(&self, list: &Self::LweList) -> LweCiphertextListOwned<u64> {
+        list.clone()
+    }
+
+    fn split_lwe(&self, list: &Self::LweList, chunk_len: usize) -> Vec<Self::LweList> {
+        split_lwe_list(list, chunk_len)
+    }
+
+    fn encrypted_pbs(
+        &self,
+        cts_in: Self::LweList,
+        luts: Self::GlweList,
+        output_indices: Vec<u64>,
+        num_total_blocks: usize,
+        num_output_blocks: usize,
+        lwe_size: LweSize,
+        short_params: &ShortintParameterSet,
+        key: &Self::EvaluationKey,
+    ) -> Self::LweList {
+        cpu::encrypted_pbs(
+            cts_in,
+            luts,
+            output_indices,
+            num_total_blocks,
+            num_output_blocks,
+            lwe_size,
+            short_params,
+            &key.fourier_bsk,
+        )
+    }
+
+    fn sum(&self, input: &mut Self::LweList, blocks: usize, num_cts: usize, key: &Self::EvaluationKey, params: MultiBitPBSParameters) -> Result<Self::LweList> {
+        cpu::sum(input, blocks, num_cts, &key.integer_key, params)
+    }
+
+    fn comparison(&self, input: &Self::LweList, scalar: u64, key: &Self::EvaluationKey, op: ComparisonType, params: MultiBitPBSParameters) -> Self::LweList {
+        cpu::comparison(input, scalar, &key.integer_key, op, params)
+    }
+
+    fn add_assign(&self, lhs: &mut Self::LweList, rhs: &Self::LweList) {
+        lhs.iter_mut().zip(rhs.iter()).for_each(|(mut lhs, rhs)| lwe_ciphertext_add_assign(&mut lhs, &rhs));
+    }
+
+    fn comparison_ct(&self, lhs: &Self::LweList, rhs: &Self::LweList, key: &Self::EvaluationKey, op: ComparisonType, params: MultiBitPBSParameters) -> Self::LweList {
+        comparison_ct(lhs, rhs, &key.integer_key, op, params)
+    }
+
+    fn top_k(&self, scores: &[Self::LweList], decisions: &[Self::LweList], k: usize, key: &Self::EvaluationKey, params: MultiBitPBSParameters) -> Result<Vec<(Self::LweList, Self::LweList)>> {
+        top_k_lwe(scores, decisions, k, &key.integer_key, params)
+    }
+}
@@ -0,0 +1,24 @@
+use tfhe::core_crypto::prelude::*;
+use tfhe::integer::ServerKey;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::backend::ComparisonType;
+use crate::common::radix::{block_to_lwe_list, lwe_list_to_radix};
+
+pub fn comparison(
+    input: &LweCiphertextListOwned<u64>,
+    scalar: u64,
+    server_key: &ServerKey,
+    op: ComparisonType,
+    params: MultiBitPBSParameters) -> LweCiphertextListOwned<u64>
+{
+    let radix = lwe_list_to_radix(input.as_view(), params);
+    let result = match op {
+        ComparisonType::GT => server_key.unchecked_scalar_gt_parallelized(&radix, scalar),
+        ComparisonType::GE => server_key.unchecked_scalar_ge_parallelized(&radix, scalar),
+        ComparisonType::LT => server_key.unchecked_scalar_lt_parallelized(&radix, scalar),
+        ComparisonType::LE => server_key.unchecked_scalar_le_parallelized(&radix, scalar),
+        ComparisonType::EQ => server_key.unchecked_scalar_eq_parallelized(&radix, scalar),
+        ComparisonType::NE => server_key.unchecked_scalar_ne_parallelized(&radix, scalar),
+    };
+    block_to_lwe_list(&result.into_raw_parts())
+}
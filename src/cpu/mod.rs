@@ -0,0 +1,9 @@
+mod sum;
+mod comparison;
+mod pbs;
+mod backend;
+
+pub use sum::*;
+pub use comparison::*;
+pub use pbs::*;
+pub use backend::*;
@@ -0,0 +1,75 @@
+use rayon::prelude::*;
+use tfhe::core_crypto::prelude::*;
+use tfhe::shortint::ShortintParameterSet;
+use crate::common::tfhe_utils::{make_accumulator_list, new_ct_list};
+use crate::common::util::luts_to_closures;
+
+// The blocks are already bootstrapped in parallel, so each PBS runs single threaded
+const PBS_THREAD_COUNT: ThreadCount = ThreadCount(1);
+
+#[allow(clippy::too_many_arguments)]
+pub fn pbs(
+    cts_in: LweCiphertextListOwned<u64>,
+    luts: Vec<Vec<u64>>,
+    output_indices: Vec<u64>,
+    num_total_blocks: usize,
+    num_output_blocks: usize,
+    lwe_size: LweSize,
+    short_params: &ShortintParameterSet,
+    bsk: &FourierLweMultiBitBootstrapKeyOwned,
+) -> LweCiphertextListOwned<u64> {
+    let functions = luts_to_closures(luts);
+    let accumulator = make_accumulator_list(&functions, short_params);
+
+    encrypted_pbs(
+        cts_in,
+        accumulator,
+        output_indices,
+        num_total_blocks,
+        num_output_blocks,
+        lwe_size,
+        short_params,
+        bsk,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn encrypted_pbs(
+    cts_in: LweCiphertextListOwned<u64>,
+    accumulator: GlweCiphertextListOwned<u64>,
+    output_indices: Vec<u64>,
+    num_total_blocks: usize,
+    num_output_blocks: usize,
+    lwe_size: LweSize,
+    short_params: &ShortintParameterSet,
+    bsk: &FourierLweMultiBitBootstrapKeyOwned,
+) -> LweCiphertextListOwned<u64> {
+    let pbs_results = (0..num_total_blocks).into_par_iter().map(|idx| {
+        let ct_in = cts_in.get(idx);
+        let lut = accumulator.get(idx);
+        let mut ct_out = LweCiphertext::new(0, lwe_size, short_params.ciphertext_modulus());
+
+        multi_bit_programmable_bootstrap_lwe_ciphertext(
+            &ct_in,
+            &mut ct_out,
+            &lut,
+            bsk,
+            PBS_THREAD_COUNT,
+            false,
+        );
+
+        (output_indices[idx] as usize, ct_out)
+    }).collect::<Vec<_>>();
+
+    // Blocks that are not written by a LUT stay trivial encryptions of zero
+    let mut out_pbs_ct = new_ct_list(
+        num_output_blocks,
+        lwe_size,
+        short_params,
+    );
+    pbs_results.into_iter().for_each(|(out_idx, ct)| {
+        out_pbs_ct.get_mut(out_idx).as_mut().copy_from_slice(ct.as_ref());
+    });
+
+    out_pbs_ct
+}
@@ -0,0 +1,24 @@
+use itertools::Itertools;
+use tfhe::core_crypto::prelude::*;
+use tfhe::integer::ServerKey;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::{Error, Result};
+use crate::common::radix::{lwe_list_to_radix, radix_to_lwe_list};
+
+pub fn sum(
+    input: &LweCiphertextListOwned<u64>, blocks: usize, num_cts: usize,
+    server_key: &ServerKey,
+    params: MultiBitPBSParameters) -> Result<LweCiphertextListOwned<u64>> {
+    // The input holds num_cts radix ciphertexts of `blocks` blocks each
+    if input.lwe_ciphertext_count().0 < blocks * num_cts {
+        return Err(Error::shape("blocks to sum", blocks * num_cts, input.lwe_ciphertext_count().0));
+    }
+    let radix_cts = input.chunks(blocks)
+        .take(num_cts)
+        .map(|chunk| lwe_list_to_radix(chunk, params))
+        .collect_vec();
+
+    let result = server_key.unchecked_sum_ciphertexts_vec_parallelized(radix_cts)
+        .ok_or_else(|| Error::shape("radix ciphertexts to sum", 1, 0))?;
+    radix_to_lwe_list(&result)
+}
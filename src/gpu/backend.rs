@@ -0,0 +1,122 @@
+use tfhe::core_crypto::gpu::glwe_ciphertext_list::CudaGlweCiphertextList;
+use tfhe::core_crypto::gpu::lwe_ciphertext_list::CudaLweCiphertextList;
+use tfhe::core_crypto::gpu::lwe_keyswitch_key::CudaLweKeyswitchKey;
+use tfhe::core_crypto::gpu::lwe_multi_bit_bootstrap_key::CudaLweMultiBitBootstrapKey;
+use tfhe::core_crypto::gpu::{cuda_lwe_ciphertext_add_assign, CudaStreams};
+use tfhe::core_crypto::prelude::*;
+use tfhe::shortint::{MultiBitPBSParameters, ShortintParameterSet};
+use crate::common::backend::{Backend, ComparisonType};
+use crate::common::error::Result;
+use crate::common::keygen::host_integer_key;
+use crate::common::keys::ServerKey;
+use crate::common::radix::comparison_ct;
+use crate::common::ranking::top_k_lwe;
+use crate::gpu;
+use crate::gpu::keygen::server_keys_cuda;
+
+pub struct GpuBackend {
+    streams: CudaStreams,
+}
+
+pub struct GpuEvaluationKey {
+    pub bsk: CudaLweMultiBitBootstrapKey,
+    pub ksk: CudaLweKeyswitchKey<u64>,
+    // The ranking and the radix against radix comparison have no CUDA implementation and run
+    // on the host
+    pub integer_key: tfhe::integer::ServerKey,
+}
+
+impl GpuBackend {
+    pub fn new(gpu_idx: u32) -> Self {
+        GpuBackend { streams: CudaStreams::new_single_gpu(gpu_idx) }
+    }
+}
+
+fn to_cuda_comparison(op: ComparisonType) -> tfhe::integer::gpu::ComparisonType {
+    match op {
+        ComparisonType::GT => tfhe::integer::gpu::ComparisonType::GT,
+        ComparisonType::GE => tfhe::integer::gpu::ComparisonType::GE,
+        ComparisonType::LT => tfhe::integer::gpu::ComparisonType::LT,
+        ComparisonType::LE => tfhe::integer::gpu::ComparisonType::LE,
+        ComparisonType::EQ => tfhe::integer::gpu::ComparisonType::EQ,
+        ComparisonType::NE => tfhe::integer::gpu::ComparisonType::NE,
+    }
+}
+
+impl Backend for GpuBackend {
+    type LweList = CudaLweCiphertextList<u64>;
+    type GlweList = CudaGlweCiphertextList<u64>;
+    type EvaluationKey = GpuEvaluationKey;
+
+    fn name(&self) -> &'static str {
+        "gpu"
+    }
+
+    fn evaluation_key(&self, server_key: &ServerKey) -> Self::EvaluationKey {
+        let (bsk, ksk) = server_keys_cuda(&server_key.bootstrap_key, &server_key.key_switching_key, &self.streams);
+        let integer_key = host_integer_key(&server_key.bootstrap_key, &server_key.key_switching_key, server_key.params);
+        GpuEvaluationKey { bsk, ksk, integer_key }
+    }
+
+    fn lwe_to_backend(&self, list: &LweCiphertextListOwned<u64>) -> Self::LweList {
+        CudaLweCiphertextList::from_lwe_ciphertext_list(list, &self.streams)
+    }
+
+    fn glwe_to_backend(&self, list: &GlweCiphertextListOwned<u64>) -> Self::GlweList {
+        CudaGlweCiphertextList::from_glwe_ciphertext_list(list, &self.streams)
+    }
+
+    fn lwe_to_host(&self, list: &Self::LweList) -> LweCiphertextListOwned<u64> {
+        list.to_lwe_ciphertext_list(&self.streams)
+    }
+
+    fn encrypted_pbs(
+        &self,
+        cts_in: Self::LweList,
+        luts: Self::GlweList,
+        output_indices: Vec<u64>,
+        num_total_blocks: usize,
+        num_output_blocks: usize,
+        lwe_size: LweSize,
+        short_params: &ShortintParameterSet,
+        key: &Self::EvaluationKey,
+    ) -> Self::LweList {
+        gpu::encrypted_pbs(
+            cts_in,
+            luts,
+            output_indices,
+            num_total_blocks,
+            num_output_blocks,
+            lwe_size,
+            short_params,
+            &key.bsk,
+            &self.streams,
+        )
+    }
+
+    fn sum(&self, input: &mut Self::LweList, blocks: usize, num_cts: usize, key: &Self::EvaluationKey, params: MultiBitPBSParameters) -> Result<Self::LweList> {
+        gpu::sum(input, blocks, num_cts, &key.bsk, &key.ksk, params, &self.streams)
+    }
+
+    fn comparison(&self, input: &Self::LweList, scalar: u64, key: &Self::EvaluationKey, op: ComparisonType, params: MultiBitPBSParameters) -> Self::LweList {
+        gpu::comparison(input, scalar, &key.bsk, &key.ksk, to_cuda_comparison(op), params, &self.streams)
+    }
+
+    fn add_assign(&self, lhs: &mut Self::LweList, rhs: &Self::LweList) {
+        cuda_lwe_ciphertext_add_assign(lhs, rhs, &self.streams);
+    }
+
+    fn comparison_ct(&self, lhs: &Self::LweList, rhs: &Self::LweList, key: &Self::EvaluationKey, op: ComparisonType, params: MultiBitPBSParameters) -> Self::LweList {
+        let result = comparison_ct(&self.lwe_to_host(lhs), &self.lwe_to_host(rhs), &key.integer_key, op, params);
+        self.lwe_to_backend(&result)
+    }
+
+    fn top_k(&self, scores: &[Self::LweList], decisions: &[Self::LweList], k: usize, key: &Self::EvaluationKey, params: MultiBitPBSParameters) -> Result<Vec<(Self::LweList, Self::LweList)>> {
+        let scores = scores.iter().map(|score| self.lwe_to_host(score)).collect::<Vec<_>>();
+        let decisions = decisions.iter().map(|decision| self.lwe_to_host(decision)).collect::<Vec<_>>();
+        Ok(top_k_lwe(&scores, &decisions, k, &key.integer_key, params)?
+            .iter()
+            .map(|(index, decision)| (self.lwe_to_backend(index), self.lwe_to_backend(decision)))
+            .collect())
+    }
+}
@@ -2,9 +2,9 @@ mod sum;
 mod keygen;
 mod comparison;
 mod pbs;
-mod auth;
+mod backend;
 
 pub use sum::*;
 pub use comparison::*;
 pub use pbs::*;
-pub use auth::*;
\ No newline at end of file
+pub use backend::*;
@@ -4,12 +4,21 @@ use tfhe::core_crypto::gpu::lwe_keyswitch_key::CudaLweKeyswitchKey;
 use tfhe::core_crypto::gpu::lwe_multi_bit_bootstrap_key::CudaLweMultiBitBootstrapKey;
 use tfhe::core_crypto::prelude::LweCiphertextCount;
 use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::{Error, Result};
 
 pub fn sum(
     input: &mut CudaLweCiphertextList<u64>, blocks: usize, num_cts: usize,
     bsk: &CudaLweMultiBitBootstrapKey,
     ksk: &CudaLweKeyswitchKey<u64>,
-    params: MultiBitPBSParameters, stream: &CudaStreams) -> CudaLweCiphertextList<u64> {
+    params: MultiBitPBSParameters, stream: &CudaStreams) -> Result<CudaLweCiphertextList<u64>> {
+    // The kernel reads num_cts radix ciphertexts of `blocks` blocks each without any bounds check,
+    // fail like the CPU sum instead
+    if num_cts == 0 {
+        return Err(Error::shape("radix ciphertexts to sum", 1, 0));
+    }
+    if input.lwe_ciphertext_count().0 < blocks * num_cts {
+        return Err(Error::shape("blocks to sum", blocks * num_cts, input.lwe_ciphertext_count().0));
+    }
     let mut result = CudaLweCiphertextList::new(
         params.glwe_dimension.to_equivalent_lwe_dimension(params.polynomial_size),
         LweCiphertextCount(blocks),
@@ -26,5 +35,5 @@ pub fn sum(
         params,
         stream,
     );
-    result
+    Ok(result)
 }
\ No newline at end of file
@@ -0,0 +1,41 @@
+//! Secure biometric authentication with TFHE.
+//!
+//! A template is enrolled by encrypting its HELR lookup tables, a probe is encrypted feature by
+//! feature, and the server evaluates the encrypted tables on the encrypted probe and compares the
+//! resulting score with the reject and accept thresholds. Only the client key holder learns the
+//! decision: reject, step-up (a second factor is required) or accept.
+//!
+//! ```no_run
+//! use encrypted_biometrics::{decrypt_decision, encrypt_probe, enroll, generate_keys, verify, Backend, Dataset};
+//! use encrypted_biometrics::common::config::{config_by_name, DEFAULT_MANIFEST};
+//! use encrypted_biometrics::cpu::CpuBackend;
+//!
+//! # fn main() -> encrypted_biometrics::Result<()> {
+//! let dataset = Dataset::load(config_by_name(DEFAULT_MANIFEST, "PUT")?)?;
+//! let (mut client_key, server_key) = generate_keys(&dataset);
+//! let evaluation_key = CpuBackend.evaluation_key(&server_key);
+//!
+//! let (probe, template) = dataset.sample(0)?;
+//! let encrypted_template = enroll(&mut client_key, &dataset, &template)?;
+//! let encrypted_probe = encrypt_probe(&mut client_key, &dataset, &probe)?;
+//! let decision = verify(&CpuBackend, &evaluation_key, &encrypted_template, &encrypted_probe)?;
+//! println!("Decision: {}", decrypt_decision(&client_key, &decision)?);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod common;
+#[cfg(feature = "cpu")]
+pub mod cpu;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod net;
+mod api;
+
+pub use api::*;
+pub use common::backend::{Backend, BackendKind, ComparisonType};
+pub use common::ciphertexts::{Decision, EncryptedCandidate, EncryptedDecision, EncryptedIdentification, EncryptedProbe, EncryptedTemplate};
+pub use common::config::Config;
+pub use common::error::{Error, Result};
+pub use common::keys::{ClientKey, ServerKey};
+pub use common::store::TemplateStore;
@@ -1,13 +1,141 @@
-use crate::common::config::{BMDB1, FRGC, PUT};
+use encrypted_biometrics::common::auth::auth;
+use encrypted_biometrics::common::config::{load_manifest, DEFAULT_MANIFEST};
+use encrypted_biometrics::common::util::arg_value;
+use encrypted_biometrics::{decrypt_decision, encrypt_probe, enroll, generate_keys, load_or_generate_keys, replace_keys, verify, Backend, PairKind, BackendKind, Config, Dataset, Error, Result, TemplateStore};
+#[cfg(feature = "cpu")]
+use encrypted_biometrics::cpu::CpuBackend;
+#[cfg(feature = "gpu")]
+use encrypted_biometrics::gpu::GpuBackend;
+use std::process::exit;
+use std::time::Instant;
 
-mod common;
-mod gpu;
+fn default_backend() -> BackendKind {
+    if cfg!(feature = "gpu") {
+        BackendKind::Gpu
+    } else {
+        BackendKind::Cpu
+    }
+}
+
+fn backend_from_args() -> BackendKind {
+    arg_value("--backend")
+        .map(|name| name.parse().unwrap_or_else(|err: String| {
+            eprintln!("{}", err);
+            exit(2)
+        }))
+        .unwrap_or_else(default_backend)
+}
+
+// --pair genuine|impostor compares two real samples instead of a sample and its noisy copy
+fn pair_from_args() -> Option<PairKind> {
+    arg_value("--pair").map(|name| match name.as_str() {
+        "genuine" => PairKind::Genuine,
+        "impostor" => PairKind::Impostor,
+        other => {
+            eprintln!("Unknown pair kind '{}', expected 'genuine' or 'impostor'", other);
+            exit(2)
+        }
+    })
+}
+
+fn pair_or_sample(dataset: &Dataset, kind: PairKind) -> Result<(Vec<u64>, Vec<u64>)> {
+    let Some(pair) = dataset.select_pairs(kind, 1)?.pop() else {
+        println!("No {:?} pair in {}, using sample 0", kind, dataset.config.data_set_name);
+        return dataset.sample(0);
+    };
+    println!("{:?} pair: probe {}, template {}", pair.kind, pair.probe_index, pair.template_index);
+    Ok((pair.probe, pair.template))
+}
+
+fn demo<B: Backend>(config: Config, backend: &B, pair: Option<PairKind>) -> Result<()> {
+    println!("Running {} on the {} backend:", config.data_set_name, backend.name());
+    let dataset = Dataset::load(config)?;
+
+    println!("Key gen...");
+    // --keys <dir> reuses the keys stored in dir instead of generating new ones on every run,
+    // --force-keys replaces them
+    let (mut client_key, server_key) = match arg_value("--keys") {
+        Some(dir) if std::env::args().any(|arg| arg == "--force-keys") => replace_keys(&dataset, &dir)?,
+        Some(dir) => load_or_generate_keys(&dataset, &dir)?,
+        None => generate_keys(&dataset),
+    };
+    let evaluation_key = backend.evaluation_key(&server_key);
+
+    println!("Enroll...");
+    let (probe, template) = match pair {
+        Some(kind) => pair_or_sample(&dataset, kind)?,
+        None => dataset.sample(0)?,
+    };
+    // --store <dir> enrolls the subject once and verifies against the stored template afterwards,
+    // which only makes sense together with --keys
+    let encrypted_template = match arg_value("--store") {
+        Some(dir) => {
+            let store = TemplateStore::open(&dir)?;
+            let subject_id = format!("{}-0", dataset.config.data_set_name);
+            match store.get(&subject_id)? {
+                Some(stored) => stored,
+                None => {
+                    let encrypted_template = enroll(&mut client_key, &dataset, &template)?;
+                    store.insert(&subject_id, &encrypted_template)?;
+                    encrypted_template
+                }
+            }
+        }
+        None => enroll(&mut client_key, &dataset, &template)?,
+    };
+    let encrypted_probe = encrypt_probe(&mut client_key, &dataset, &probe)?;
+
+    let start = Instant::now();
+    let decision = verify(backend, &evaluation_key, &encrypted_template, &encrypted_probe)?;
+    let elapsed = start.elapsed();
+
+    let expected = dataset.reference(&probe, &template)?.decision;
+    println!("Got: {} (expected {})", decrypt_decision(&client_key, &decision)?, expected);
+    println!("Total time needed: {}s", elapsed.as_secs_f64());
+    Ok(())
+}
+
+fn run(config: Config, kind: BackendKind, pair: Option<PairKind>, check_stages: bool) -> Result<()> {
+    // --trace <dir> decrypts the intermediate stages of --check into <dir>/<dataset>_trace.json
+    let trace_path = arg_value("--trace").map(|dir| format!("{}/{}_trace.json", dir, config.data_set_name));
+    let trace_path = trace_path.as_deref();
+    match kind {
+        #[cfg(feature = "gpu")]
+        BackendKind::Gpu if check_stages => auth(config, &GpuBackend::new(0), trace_path),
+        #[cfg(feature = "gpu")]
+        BackendKind::Gpu => demo(config, &GpuBackend::new(0), pair),
+        #[cfg(feature = "cpu")]
+        BackendKind::Cpu if check_stages => auth(config, &CpuBackend, trace_path),
+        #[cfg(feature = "cpu")]
+        BackendKind::Cpu => demo(config, &CpuBackend, pair),
+        #[allow(unreachable_patterns)]
+        _ => Err(Error::BackendNotEnabled(format!("{:?}", kind).to_lowercase())),
+    }
+}
 
 fn main() {
     println!("Hello, world!");
-    gpu::auth(PUT);
-    gpu::auth(BMDB1);
-    gpu::auth(FRGC);
+    let kind = backend_from_args();
+    let pair = pair_from_args();
+    // --check runs the stages one by one and compares the decision with the plaintext reference
+    let check_stages = std::env::args().any(|arg| arg == "--check") || arg_value("--trace").is_some();
+    // --manifest <file> reads the datasets from another manifest
+    let manifest_path = arg_value("--manifest").unwrap_or_else(|| DEFAULT_MANIFEST.to_string());
+    let configs = load_manifest(&manifest_path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1)
+    });
+    let mut failed = false;
+    for config in configs {
+        let name = config.data_set_name.clone();
+        if let Err(err) = run(config, kind, pair, check_stages) {
+            eprintln!("{} failed: {}", name, err);
+            failed = true;
+        }
+    }
 
     println!("Goodbye");
+    if failed {
+        exit(1);
+    }
 }
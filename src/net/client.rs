@@ -0,0 +1,86 @@
+use std::net::TcpStream;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::ciphertexts::{Decision, EncryptedCandidate, EncryptedDecision, EncryptedIdentification, EncryptedProbe};
+use crate::common::error::{Error, Result};
+use crate::net::protocol::{expect_message, write_message, Message, PROTOCOL_VERSION};
+use crate::net::session::{new_nonce, Role, Session};
+
+// Client side of a verification session, the client key never leaves the caller
+pub struct Client {
+    stream: TcpStream,
+    session: Session,
+}
+
+impl Client {
+    // `psk` is the key shared with the server, it authenticates every frame after the handshake
+    pub fn connect(addr: &str, dataset: &str, params: MultiBitPBSParameters, psk: &[u8]) -> Result<Self> {
+        let mut stream = TcpStream::connect(addr).map_err(|err| Error::io(addr, err))?;
+        let client_nonce = new_nonce();
+        write_message(&mut stream, &Message::Hello {
+            version: PROTOCOL_VERSION,
+            dataset: dataset.to_string(),
+            params,
+            nonce: client_nonce,
+        })?;
+        let server_nonce = match expect_message(&mut stream)? {
+            Message::HelloAck { version, params: server_params, nonce } => {
+                if version != PROTOCOL_VERSION {
+                    return Err(Error::Protocol(format!("server speaks protocol version {}", version)));
+                }
+                if server_params != params {
+                    return Err(Error::ParameterMismatch(format!("server {}", addr)));
+                }
+                nonce
+            }
+            other => return Err(Error::Protocol(format!("expected HelloAck, got {}", other.name()))),
+        };
+        let session = Session::new(psk, dataset, &params, &client_nonce, &server_nonce, Role::Client);
+        Ok(Client { stream, session })
+    }
+
+    pub fn verify(&mut self, subject_id: &str, probe: EncryptedProbe) -> Result<EncryptedDecision> {
+        self.session.send(&mut self.stream, &Message::ProbeUpload {
+            subject_id: subject_id.to_string(),
+            probe,
+        })?;
+        match self.session.expect(&mut self.stream)? {
+            Message::Result { decision } => Ok(decision),
+            other => Err(Error::Protocol(format!("expected Result, got {}", other.name()))),
+        }
+    }
+
+    // Reports the decision decrypted from the last Result, the server records it in its audit log
+    pub fn report_decision(&mut self, decision: Decision) -> Result<()> {
+        self.session.send(&mut self.stream, &Message::DecisionReport { decision })
+    }
+
+    // Identification against every subject enrolled on the server, returns the subject ids and
+    // the encrypted decisions and best match
+    pub fn identify(&mut self, probe: EncryptedProbe) -> Result<(Vec<String>, EncryptedIdentification)> {
+        self.session.send(&mut self.stream, &Message::IdentifyUpload { probe })?;
+        match self.session.expect(&mut self.stream)? {
+            Message::Identification { subject_ids, identification } => {
+                if subject_ids.len() != identification.decisions.len() {
+                    return Err(Error::shape("identification decisions", subject_ids.len(), identification.decisions.len()));
+                }
+                Ok((subject_ids, identification))
+            }
+            other => Err(Error::Protocol(format!("expected Identification, got {}", other.name()))),
+        }
+    }
+
+    // Encrypted top-k over the subjects enrolled on the server, returns the subject ids and the
+    // `k` best candidates
+    pub fn rank(&mut self, probe: EncryptedProbe, k: u32) -> Result<(Vec<String>, Vec<EncryptedCandidate>)> {
+        self.session.send(&mut self.stream, &Message::RankUpload { probe, k })?;
+        match self.session.expect(&mut self.stream)? {
+            Message::Ranking { subject_ids, candidates } => {
+                if candidates.len() != k as usize {
+                    return Err(Error::shape("ranked candidates", k as usize, candidates.len()));
+                }
+                Ok((subject_ids, candidates))
+            }
+            other => Err(Error::Protocol(format!("expected Ranking, got {}", other.name()))),
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod protocol;
+pub mod session;
+pub mod server;
+pub mod client;
+pub mod rate_limit;
@@ -0,0 +1,143 @@
+use std::io::{ErrorKind, Read, Write};
+use serde::{Deserialize, Serialize};
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::ciphertexts::{Decision, EncryptedCandidate, EncryptedDecision, EncryptedIdentification, EncryptedProbe};
+use crate::common::error::{Error, Result};
+use crate::net::session::Nonce;
+
+pub const PROTOCOL_VERSION: u32 = 4;
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+// Large enough for a probe batch, small enough to refuse garbage length prefixes
+const MAX_FRAME_LEN: usize = 1 << 30;
+// Limit of the unauthenticated handshake and error frames, which are read before the peer has
+// shown anything, so that a bare length prefix can not make the server allocate MAX_FRAME_LEN
+const MAX_HANDSHAKE_FRAME_LEN: usize = 1 << 16;
+
+// Messages exchanged between client and server. A session starts with Hello/HelloAck, in which
+// the server confirms the dataset and announces its parameters and both sides contribute a
+// nonce to the session key. The handshake is followed by any number of authenticated
+// ProbeUpload/Result (1:1 verification), IdentifyUpload/Identification (1:N identification) and
+// RankUpload/Ranking (encrypted top-k) exchanges. After a Result the client reports the decision
+// it decrypted with a DecisionReport, which is not answered. Failures are reported with an Error
+// message.
+// bincode encodes the variant index, so new variants are only ever appended and every change of
+// the messages bumps PROTOCOL_VERSION.
+#[derive(Serialize, Deserialize)]
+pub enum Message {
+    Hello {
+        version: u32,
+        dataset: String,
+        params: MultiBitPBSParameters,
+        nonce: Nonce,
+    },
+    HelloAck {
+        version: u32,
+        params: MultiBitPBSParameters,
+        nonce: Nonce,
+    },
+    ProbeUpload {
+        subject_id: String,
+        probe: EncryptedProbe,
+    },
+    Result {
+        decision: EncryptedDecision,
+    },
+    Error {
+        message: String,
+    },
+    IdentifyUpload {
+        probe: EncryptedProbe,
+    },
+    // One decision per enrolled subject, in the order of `subject_ids`, and the best match, whose
+    // index refers to `subject_ids`
+    Identification {
+        subject_ids: Vec<String>,
+        identification: EncryptedIdentification,
+    },
+    RankUpload {
+        probe: EncryptedProbe,
+        k: u32,
+    },
+    // The `k` best candidates, best first, their indices refer to `subject_ids`
+    Ranking {
+        subject_ids: Vec<String>,
+        candidates: Vec<EncryptedCandidate>,
+    },
+    DecisionReport {
+        decision: Decision,
+    },
+}
+
+impl Message {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "Hello",
+            Message::HelloAck { .. } => "HelloAck",
+            Message::ProbeUpload { .. } => "ProbeUpload",
+            Message::Result { .. } => "Result",
+            Message::Error { .. } => "Error",
+            Message::IdentifyUpload { .. } => "IdentifyUpload",
+            Message::Identification { .. } => "Identification",
+            Message::RankUpload { .. } => "RankUpload",
+            Message::Ranking { .. } => "Ranking",
+            Message::DecisionReport { .. } => "DecisionReport",
+        }
+    }
+}
+
+// Frames are a big endian u32 length followed by the payload
+pub fn write_frame<W: Write>(stream: &mut W, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(Error::Protocol(format!("frame of {} bytes exceeds the limit", payload.len())));
+    }
+    stream.write_all(&(payload.len() as u32).to_be_bytes()).map_err(|err| Error::io("connection", err))?;
+    stream.write_all(payload).map_err(|err| Error::io("connection", err))?;
+    stream.flush().map_err(|err| Error::io("connection", err))
+}
+
+// Returns None if the peer closed the connection between two frames
+pub fn read_frame<R: Read>(stream: &mut R) -> Result<Option<Vec<u8>>> {
+    read_frame_limited(stream, MAX_FRAME_LEN)
+}
+
+// Like read_frame, refusing frames longer than `max_len` before allocating them
+fn read_frame_limited<R: Read>(stream: &mut R, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let mut len_bytes = [0u8; 4];
+    match stream.read_exact(&mut len_bytes) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(Error::io("connection", err)),
+    }
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > max_len {
+        return Err(Error::Protocol(format!("frame of {} bytes exceeds the limit of {}", len, max_len)));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).map_err(|err| Error::io("connection", err))?;
+    Ok(Some(payload))
+}
+
+// Unauthenticated bincode encoded message, only used for the handshake and for errors before a
+// session exists, limited to MAX_HANDSHAKE_FRAME_LEN. Everything else goes through a Session.
+pub fn write_message<W: Write>(stream: &mut W, message: &Message) -> Result<()> {
+    let payload = bincode::serialize(message).map_err(|err| Error::format("message", &err.to_string()))?;
+    write_frame(stream, &payload)
+}
+
+pub fn read_message<R: Read>(stream: &mut R) -> Result<Option<Message>> {
+    let Some(payload) = read_frame_limited(stream, MAX_HANDSHAKE_FRAME_LEN)? else {
+        return Ok(None);
+    };
+    bincode::deserialize(&payload)
+        .map(Some)
+        .map_err(|err| Error::format("message", &err.to_string()))
+}
+
+pub fn expect_message<R: Read>(stream: &mut R) -> Result<Message> {
+    match read_message(stream)? {
+        Some(Message::Error { message }) => Err(Error::Protocol(format!("peer reported: {}", message))),
+        Some(message) => Ok(message),
+        None => Err(Error::Protocol("connection closed".to_string())),
+    }
+}
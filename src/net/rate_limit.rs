@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::common::ciphertexts::Decision;
+use crate::common::error::{Error, Result};
+
+// Source of the current time in seconds, replaced by a SimulatedClock to test the policies
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+// Clock that only moves when told to. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct SimulatedClock {
+    now: Arc<AtomicU64>,
+}
+
+impl SimulatedClock {
+    pub fn new(start: u64) -> Self {
+        SimulatedClock { now: Arc::new(AtomicU64::new(start)) }
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+// Limits on the verifications of one subject, and on the identifications and rankings of one
+// client. Rejections are the decisions reported by the client, a verification it did not report a
+// decision for counts as a rejection. A reported accept or step-up does not clear earlier
+// rejections, they start over once a whole window passed without an attempt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitPolicy {
+    // At most `max_attempts` verifications of a subject, and as many identifications and rankings
+    // of a client, within `window_secs`
+    pub max_attempts: u32,
+    pub window_secs: u64,
+    // After the n-th consecutive rejection the next attempt has to wait base * 2^(n-1) seconds,
+    // at most `backoff_max_secs`
+    pub backoff_base_secs: u64,
+    pub backoff_max_secs: u64,
+    // That many consecutive rejections lock the subject for `lockout_secs`
+    pub lockout_after: u32,
+    pub lockout_secs: u64,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        RateLimitPolicy {
+            max_attempts: 10,
+            window_secs: 300,
+            backoff_base_secs: 1,
+            backoff_max_secs: 300,
+            lockout_after: 10,
+            lockout_secs: 3600,
+        }
+    }
+}
+
+impl RateLimitPolicy {
+    // Missing fields take their default value
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+        let policy: RateLimitPolicy = serde_json::from_str(&content).map_err(|err| Error::format(path, &err.to_string()))?;
+        if policy.max_attempts == 0 || policy.lockout_after == 0 {
+            return Err(Error::format(path, "max_attempts and lockout_after must be at least 1"));
+        }
+        Ok(policy)
+    }
+
+    fn backoff(&self, rejections: u32) -> u64 {
+        let factor = 1u64.checked_shl(rejections.saturating_sub(1)).unwrap_or(u64::MAX);
+        self.backoff_base_secs.saturating_mul(factor).min(self.backoff_max_secs)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct SubjectState {
+    // Start times of the attempts within the current window
+    attempts: Vec<u64>,
+    consecutive_rejections: u32,
+    next_attempt: u64,
+    locked_until: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct LimiterState {
+    subjects: HashMap<String, SubjectState>,
+    // Start times of the identifications and rankings of every client address within the window
+    clients: HashMap<String, Vec<u64>>,
+}
+
+// Rate limiting of the verification server: per subject for verifications, per client for
+// identifications and rankings, which evaluate every subject at once. The state is written to
+// `state_path` after every change and read back on start, so restarting the server does not
+// reset it.
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    clock: Box<dyn Clock>,
+    state_path: String,
+    state: LimiterState,
+}
+
+impl RateLimiter {
+    pub fn open(policy: RateLimitPolicy, clock: Box<dyn Clock>, state_path: &str) -> Result<Self> {
+        let state = match fs::read_to_string(state_path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|err| Error::format(state_path, &err.to_string()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => LimiterState::default(),
+            Err(err) => return Err(Error::io(state_path, err)),
+        };
+        Ok(RateLimiter { policy, clock, state_path: state_path.to_string(), state })
+    }
+
+    // Admits a verification of `subject_id` and counts it, or fails with the time to wait
+    pub fn begin_attempt(&mut self, subject_id: &str) -> Result<()> {
+        let now = self.clock.now();
+        let policy = &self.policy;
+        let state = self.state.subjects.entry(subject_id.to_string()).or_default();
+        state.attempts.retain(|&start| start + policy.window_secs > now);
+
+        let retry_at = if state.locked_until > now {
+            Some(state.locked_until)
+        } else if state.next_attempt > now {
+            Some(state.next_attempt)
+        } else if state.attempts.len() >= policy.max_attempts as usize {
+            state.attempts.first().map(|&oldest| oldest + policy.window_secs)
+        } else {
+            None
+        };
+        if let Some(retry_at) = retry_at {
+            return Err(Error::RateLimited { limited: format!("subject '{}'", subject_id), retry_after: retry_at - now });
+        }
+        if state.attempts.is_empty() {
+            state.consecutive_rejections = 0;
+        }
+        state.attempts.push(now);
+        self.save()
+    }
+
+    // Applies the decision the client reported for the last attempt, None if it reported nothing
+    pub fn finish_attempt(&mut self, subject_id: &str, decision: Option<Decision>) -> Result<()> {
+        let now = self.clock.now();
+        let policy = &self.policy;
+        let state = self.state.subjects.entry(subject_id.to_string()).or_default();
+        match decision {
+            // Reported by the client and not verifiable by the server, so neither counts as a
+            // rejection nor clears earlier ones
+            Some(Decision::Accept) | Some(Decision::StepUp) => return Ok(()),
+            Some(Decision::Reject) | None => {
+                state.consecutive_rejections += 1;
+                state.next_attempt = now + policy.backoff(state.consecutive_rejections);
+                if state.consecutive_rejections >= policy.lockout_after {
+                    state.locked_until = now + policy.lockout_secs;
+                    state.consecutive_rejections = 0;
+                }
+            }
+        }
+        self.save()
+    }
+
+    // Fails with the time to wait if `client` used up its attempts of the window
+    pub fn check_client(&mut self, client: &str) -> Result<()> {
+        let now = self.clock.now();
+        let window_secs = self.policy.window_secs;
+        let Some(attempts) = self.state.clients.get_mut(client) else { return Ok(()) };
+        attempts.retain(|&start| start + window_secs > now);
+        if attempts.len() >= self.policy.max_attempts as usize {
+            let retry_at = attempts.first().map_or(now, |&oldest| oldest + window_secs);
+            return Err(Error::RateLimited { limited: format!("client {}", client), retry_after: retry_at - now });
+        }
+        Ok(())
+    }
+
+    // Admits an identification or ranking requested from `client`, or the lookup of an unknown
+    // subject, and counts it, or fails with the time to wait. The subjects an identification
+    // evaluates are not charged, so a client can not lock them out.
+    pub fn begin_client_attempt(&mut self, client: &str) -> Result<()> {
+        self.check_client(client)?;
+        let now = self.clock.now();
+        self.state.clients.entry(client.to_string()).or_default().push(now);
+        self.save()
+    }
+
+    // Drops the state that no longer limits anything, so unknown subject ids and clients seen
+    // once do not pile up
+    fn prune(&mut self) {
+        let now = self.clock.now();
+        let window_secs = self.policy.window_secs;
+        self.state.subjects.retain(|_, state| {
+            state.attempts.retain(|&start| start + window_secs > now);
+            !state.attempts.is_empty() || state.next_attempt > now || state.locked_until > now
+        });
+        self.state.clients.retain(|_, attempts| {
+            attempts.retain(|&start| start + window_secs > now);
+            !attempts.is_empty()
+        });
+    }
+
+    // Written to a temporary file and renamed, so a crash leaves either the old or the new state
+    fn save(&mut self) -> Result<()> {
+        self.prune();
+        let tmp_path = format!("{}.tmp", self.state_path);
+        let content = serde_json::to_string(&self.state).map_err(|err| Error::format("rate limit state", &err.to_string()))?;
+        fs::write(&tmp_path, content).map_err(|err| Error::io(&tmp_path, err))?;
+        fs::rename(&tmp_path, &self.state_path).map_err(|err| Error::io(&self.state_path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rate_limit_{}_{}.json", name, std::process::id())).to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn policy(max_attempts: u32, window_secs: u64, backoff_base_secs: u64, backoff_max_secs: u64, lockout_after: u32, lockout_secs: u64) -> RateLimitPolicy {
+        RateLimitPolicy { max_attempts, window_secs, backoff_base_secs, backoff_max_secs, lockout_after, lockout_secs }
+    }
+
+    fn retry_after(result: Result<()>) -> u64 {
+        match result {
+            Err(Error::RateLimited { retry_after, .. }) => retry_after,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(()) => panic!("attempt was admitted"),
+        }
+    }
+
+    fn attempt(limiter: &mut RateLimiter, decision: Option<Decision>) {
+        limiter.begin_attempt("alice").unwrap();
+        limiter.finish_attempt("alice", decision).unwrap();
+    }
+
+    #[test]
+    fn limits_attempts_per_window() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(policy(3, 100, 1, 300, 100, 0), Box::new(clock.clone()), &state_path("window")).unwrap();
+        for _ in 0..3 {
+            attempt(&mut limiter, Some(Decision::Accept));
+            clock.advance(10);
+        }
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 70);
+        limiter.begin_attempt("bob").unwrap();
+        clock.advance(70);
+        limiter.begin_attempt("alice").unwrap();
+    }
+
+    #[test]
+    fn backs_off_exponentially_after_rejections() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(policy(100, 1000, 2, 10, 100, 0), Box::new(clock.clone()), &state_path("backoff")).unwrap();
+        attempt(&mut limiter, Some(Decision::Reject));
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 2);
+        clock.advance(2);
+        attempt(&mut limiter, None);
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 4);
+        clock.advance(4);
+        attempt(&mut limiter, Some(Decision::Reject));
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 8);
+        clock.advance(8);
+        attempt(&mut limiter, Some(Decision::Reject));
+        // 16 seconds, capped
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 10);
+    }
+
+    #[test]
+    fn reported_accept_does_not_clear_rejections() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(policy(100, 1000, 2, 1000, 100, 0), Box::new(clock.clone()), &state_path("accept")).unwrap();
+        attempt(&mut limiter, Some(Decision::Reject));
+        clock.advance(2);
+        attempt(&mut limiter, Some(Decision::Accept));
+        attempt(&mut limiter, Some(Decision::StepUp));
+        attempt(&mut limiter, Some(Decision::Reject));
+        // Second rejection in a row
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 4);
+    }
+
+    #[test]
+    fn accepted_attempts_are_not_locked_out() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(RateLimitPolicy::default(), Box::new(clock.clone()), &state_path("genuine")).unwrap();
+        for _ in 0..30 {
+            attempt(&mut limiter, Some(Decision::Accept));
+            clock.advance(60);
+        }
+    }
+
+    #[test]
+    fn rejections_start_over_after_a_quiet_window() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(policy(100, 50, 2, 1000, 100, 0), Box::new(clock.clone()), &state_path("quiet")).unwrap();
+        attempt(&mut limiter, Some(Decision::Reject));
+        clock.advance(2);
+        attempt(&mut limiter, Some(Decision::Reject));
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 4);
+        clock.advance(50);
+        attempt(&mut limiter, Some(Decision::Reject));
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 2);
+    }
+
+    #[test]
+    fn locks_out_after_consecutive_rejections() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(policy(100, 1000, 0, 0, 3, 500), Box::new(clock.clone()), &state_path("lockout")).unwrap();
+        for _ in 0..3 {
+            attempt(&mut limiter, Some(Decision::Reject));
+        }
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 500);
+        clock.advance(499);
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 1);
+        clock.advance(1);
+        limiter.begin_attempt("alice").unwrap();
+    }
+
+    #[test]
+    fn limits_clients_without_charging_subjects() {
+        let clock = SimulatedClock::new(1000);
+        let mut limiter = RateLimiter::open(policy(2, 100, 1, 300, 1, 500), Box::new(clock.clone()), &state_path("clients")).unwrap();
+        limiter.begin_client_attempt("10.0.0.1").unwrap();
+        limiter.begin_client_attempt("10.0.0.1").unwrap();
+        assert_eq!(retry_after(limiter.begin_client_attempt("10.0.0.1")), 100);
+        limiter.begin_client_attempt("10.0.0.2").unwrap();
+        limiter.begin_attempt("alice").unwrap();
+        clock.advance(100);
+        limiter.begin_client_attempt("10.0.0.1").unwrap();
+    }
+
+    #[test]
+    fn state_survives_reopening() {
+        let clock = SimulatedClock::new(1000);
+        let path = state_path("reopen");
+        let mut limiter = RateLimiter::open(policy(100, 1000, 0, 0, 2, 500), Box::new(clock.clone()), &path).unwrap();
+        attempt(&mut limiter, Some(Decision::Reject));
+        attempt(&mut limiter, None);
+        limiter.begin_attempt("bob").unwrap();
+        limiter.finish_attempt("bob", Some(Decision::Reject)).unwrap();
+        limiter.begin_client_attempt("10.0.0.1").unwrap();
+        drop(limiter);
+
+        clock.advance(100);
+        let mut limiter = RateLimiter::open(policy(100, 1000, 0, 0, 2, 500), Box::new(clock.clone()), &path).unwrap();
+        assert_eq!(retry_after(limiter.begin_attempt("alice")), 400);
+        // The first rejection of bob was recorded before, the second one locks
+        limiter.begin_attempt("bob").unwrap();
+        limiter.finish_attempt("bob", Some(Decision::Reject)).unwrap();
+        assert_eq!(retry_after(limiter.begin_attempt("bob")), 500);
+        assert_eq!(limiter.state.clients["10.0.0.1"], vec![1000]);
+    }
+}
@@ -0,0 +1,265 @@
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use serde::Serialize;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::api::{identify, rank, verify};
+use crate::common::audit::{sha256_hex, Attempt, AuditLog, Request};
+use crate::common::backend::Backend;
+use crate::common::ciphertexts::{Decision, EncryptedProbe, EncryptedTemplate};
+use crate::common::error::{Error, Result};
+use crate::common::keys::ServerKey;
+use crate::common::store::{validate_subject_id, TemplateStore};
+use crate::net::protocol::{read_message, write_message, Message, PROTOCOL_VERSION};
+use crate::net::rate_limit::RateLimiter;
+use crate::net::session::{new_nonce, Role, Session};
+
+// Verification server, holding the server key and the encrypted templates of one dataset
+pub struct Server<B: Backend> {
+    backend: B,
+    evaluation_key: B::EvaluationKey,
+    dataset: String,
+    params: MultiBitPBSParameters,
+    store: TemplateStore,
+    psk: Vec<u8>,
+    audit_log: Option<Mutex<AuditLog>>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+}
+
+impl<B: Backend> Server<B> {
+    pub fn new(backend: B, server_key: &ServerKey, dataset: &str, store: TemplateStore, psk: Vec<u8>) -> Self {
+        let evaluation_key = backend.evaluation_key(server_key);
+        Server {
+            backend,
+            evaluation_key,
+            dataset: dataset.to_string(),
+            params: server_key.params(),
+            store,
+            psk,
+            audit_log: None,
+            rate_limiter: None,
+        }
+    }
+
+    // Records every verification in `audit_log`, together with the decision reported by the client,
+    // and every subject evaluated by an identification or ranking
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(Mutex::new(audit_log));
+        self
+    }
+
+    // Applies the attempt limits of `rate_limiter` to the verifications of every subject and to the
+    // identifications and rankings of every client address
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(Mutex::new(rate_limiter));
+        self
+    }
+
+    // Serves the connections one after the other, a failing connection does not stop the server
+    pub fn serve(&self, addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(addr).map_err(|err| Error::io(addr, err))?;
+        println!("Listening on {}", addr);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Connection failed: {}", err);
+                    continue;
+                }
+            };
+            let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+            if let Err(err) = self.handle_connection(stream) {
+                eprintln!("{}: {}", peer, err);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_connection(&self, mut stream: TcpStream) -> Result<()> {
+        let client = stream.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+        let mut session = None;
+        let mut pending = Vec::new();
+        let mut result = self.run_session(&mut stream, &client, &mut session, &mut pending);
+        // A verification the client did not report on is still recorded
+        result = result.and(self.finish_attempts(&mut pending, None));
+        if let Err(err) = &result {
+            let message = Message::Error { message: err.to_string() };
+            // Best effort, the connection may already be gone
+            let _ = match session.as_mut() {
+                Some(session) => session.send(&mut stream, &message),
+                None => write_message(&mut stream, &message),
+            };
+        }
+        result
+    }
+
+    // `pending` holds the attempts of the last request until the client reports its decision or
+    // sends the next request. Only a verification is followed by a DecisionReport.
+    fn run_session(&self, stream: &mut TcpStream, client: &str, session: &mut Option<Session>, pending: &mut Vec<Attempt>) -> Result<()> {
+        let client_nonce = match read_message(stream)? {
+            Some(Message::Hello { version, dataset, params, nonce }) => {
+                if version != PROTOCOL_VERSION {
+                    return Err(Error::Protocol(format!("unsupported protocol version {}", version)));
+                }
+                if !dataset.eq_ignore_ascii_case(&self.dataset) {
+                    return Err(Error::Protocol(format!("server runs {}, client asked for {}", self.dataset, dataset)));
+                }
+                if params != self.params {
+                    return Err(Error::ParameterMismatch("client hello".to_string()));
+                }
+                nonce
+            }
+            Some(other) => return Err(Error::Protocol(format!("expected Hello, got {}", other.name()))),
+            None => return Ok(()),
+        };
+        // A replayed Hello needs no bookkeeping: the fresh server nonce gives the replayed session a
+        // new key, under which the replayed frames fail authentication
+        let server_nonce = new_nonce();
+        write_message(stream, &Message::HelloAck { version: PROTOCOL_VERSION, params: self.params, nonce: server_nonce })?;
+        let session = session.insert(Session::new(&self.psk, &self.dataset, &self.params, &client_nonce, &server_nonce, Role::Server));
+
+        while let Some(message) = session.receive(stream)? {
+            if let Message::DecisionReport { decision } = message {
+                if !matches!(pending.as_slice(), [Attempt { request: Request::Verify, .. }]) {
+                    return Err(Error::Protocol("DecisionReport without a preceding Result".to_string()));
+                }
+                self.finish_attempts(pending, Some(decision))?;
+                continue;
+            }
+            self.finish_attempts(pending, None)?;
+            let response = match message {
+                Message::ProbeUpload { subject_id, probe } => {
+                    // Limited before the lookup, so that probing for enrolled subject ids is limited
+                    // and audited like a verification
+                    validate_subject_id(&subject_id)?;
+                    if let Some(rate_limiter) = &self.rate_limiter {
+                        let mut rate_limiter = rate_limiter.lock().expect("rate limiter poisoned");
+                        rate_limiter.check_client(client)?;
+                        rate_limiter.begin_attempt(&subject_id)?;
+                    }
+                    let Some(template) = self.store.get(&subject_id)? else {
+                        // Unknown subject ids are also charged to the client, every id has a limit
+                        // of its own
+                        if let Some(rate_limiter) = &self.rate_limiter {
+                            rate_limiter.lock().expect("rate limiter poisoned").begin_client_attempt(client)?;
+                        }
+                        *pending = vec![self.unknown_subject_attempt(&subject_id, &probe)?];
+                        return Err(Error::UnknownSubject(subject_id));
+                    };
+                    self.check_probe(&probe)?;
+                    let decision = isolate("verification", || verify(&self.backend, &self.evaluation_key, &template, &probe))?;
+                    let response_hash = sha256_hex(&decision.to_bytes()?);
+                    *pending = self.attempts(Request::Verify, vec![subject_id], &[template], &probe, response_hash)?;
+                    Message::Result { decision }
+                }
+                Message::IdentifyUpload { probe } => {
+                    let (subject_ids, templates) = self.admit_gallery(client)?;
+                    self.check_probe(&probe)?;
+                    let identification = isolate("identification", || identify(&self.backend, &self.evaluation_key, &templates, &probe))?;
+                    let response_hash = sha256_hex(&encode("identification", &identification)?);
+                    *pending = self.attempts(Request::Identify, subject_ids.clone(), &templates, &probe, response_hash)?;
+                    Message::Identification { subject_ids, identification }
+                }
+                Message::RankUpload { probe, k } => {
+                    let (subject_ids, templates) = self.admit_gallery(client)?;
+                    self.check_probe(&probe)?;
+                    let candidates = isolate("ranking", || rank(&self.backend, &self.evaluation_key, &templates, &probe, k as usize))?;
+                    let response_hash = sha256_hex(&encode("ranking", &candidates)?);
+                    *pending = self.attempts(Request::Rank, subject_ids.clone(), &templates, &probe, response_hash)?;
+                    Message::Ranking { subject_ids, candidates }
+                }
+                other => return Err(Error::Protocol(format!("expected a probe upload, got {}", other.name()))),
+            };
+            session.send(stream, &response)?;
+        }
+        Ok(())
+    }
+
+    // Probes are checked in full before they reach the PBS, which asserts on the ciphertext layout
+    fn check_probe(&self, probe: &EncryptedProbe) -> Result<()> {
+        if probe.params()? != self.params {
+            return Err(Error::ParameterMismatch("probe".to_string()));
+        }
+        probe.validate()
+    }
+
+    // One attempt per evaluated subject, all sharing the hashes of the probe and of the response
+    fn attempts(&self, request: Request, subject_ids: Vec<String>, templates: &[EncryptedTemplate], probe: &EncryptedProbe, response_hash: String) -> Result<Vec<Attempt>> {
+        let probe_hash = sha256_hex(&probe.to_bytes()?);
+        Ok(subject_ids.into_iter().zip(templates).map(|(subject_id, template)| Attempt {
+            request,
+            subject_id,
+            dataset: template.dataset.clone(),
+            num_tables: template.num_tables(),
+            decomp_base: template.decomp_base,
+            params: self.params,
+            probe_hash: probe_hash.clone(),
+            response_hash: response_hash.clone(),
+        }).collect())
+    }
+
+    // Verification of a subject id that is not enrolled, recorded without a response
+    fn unknown_subject_attempt(&self, subject_id: &str, probe: &EncryptedProbe) -> Result<Attempt> {
+        Ok(Attempt {
+            request: Request::Verify,
+            subject_id: subject_id.to_string(),
+            dataset: self.dataset.clone(),
+            num_tables: probe.decomp_lengths.len(),
+            decomp_base: probe.decomp_base,
+            params: self.params,
+            probe_hash: sha256_hex(&probe.to_bytes()?),
+            response_hash: String::new(),
+        })
+    }
+
+    // Feeds the decision reported for a verification to the rate limiter and records the pending
+    // attempts in the audit log. Identifications and rankings were limited per client when they began.
+    fn finish_attempts(&self, pending: &mut Vec<Attempt>, client_decision: Option<Decision>) -> Result<()> {
+        for attempt in pending.drain(..) {
+            if let (Some(rate_limiter), Request::Verify) = (&self.rate_limiter, attempt.request) {
+                rate_limiter.lock().expect("rate limiter poisoned").finish_attempt(&attempt.subject_id, client_decision)?;
+            }
+            if let Some(audit_log) = &self.audit_log {
+                audit_log.lock().expect("audit log poisoned").append(attempt, client_decision)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The gallery for an identification or ranking requested by `client`, which counts as one
+    // attempt of the client. The subjects are not charged, so identifications can not lock them out.
+    fn admit_gallery(&self, client: &str) -> Result<(Vec<String>, Vec<EncryptedTemplate>)> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.lock().expect("rate limiter poisoned").begin_client_attempt(client)?;
+        }
+        self.load_gallery()
+    }
+
+    // All enrolled templates of the server's dataset, with their subject ids
+    fn load_gallery(&self) -> Result<(Vec<String>, Vec<EncryptedTemplate>)> {
+        let mut subject_ids = Vec::new();
+        let mut templates = Vec::new();
+        for subject_id in self.store.list()? {
+            let Some(template) = self.store.get(&subject_id)? else { continue };
+            if template.dataset.eq_ignore_ascii_case(&self.dataset) {
+                subject_ids.push(subject_id);
+                templates.push(template);
+            }
+        }
+        if templates.is_empty() {
+            return Err(Error::Protocol(format!("no subjects enrolled for {}", self.dataset)));
+        }
+        Ok((subject_ids, templates))
+    }
+}
+
+// Runs the evaluation of a request, turning a panic in the cryptographic code into an error that is
+// sent to the client instead of taking the whole server down
+fn isolate<T>(request: &str, evaluate: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(evaluate)).unwrap_or_else(|_| Err(Error::Evaluation(request.to_string())))
+}
+
+// Encoding of a response that has no file format of its own, for its audit hash
+fn encode<T: Serialize>(what: &str, value: &T) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|err| Error::format(what, &err.to_string()))
+}
@@ -0,0 +1,241 @@
+use std::fs;
+use std::io::{Read, Write};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tfhe::shortint::MultiBitPBSParameters;
+use crate::common::error::{Error, Result};
+use crate::net::protocol::{read_frame, write_frame, Message, PROTOCOL_VERSION};
+
+pub type Nonce = [u8; 32];
+
+// Shortest accepted pre-shared key
+const MIN_PSK_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    // Direction byte of the frames sent by this side, so that a frame can not be reflected back
+    fn direction(self) -> u8 {
+        match self {
+            Role::Client => 0,
+            Role::Server => 1,
+        }
+    }
+
+    fn peer(self) -> Role {
+        match self {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        }
+    }
+}
+
+// Frame sent after the handshake. `tag` is an HMAC-SHA256 under the session key over the
+// direction, the sequence number and the payload.
+#[derive(Serialize, Deserialize)]
+struct AuthenticatedFrame {
+    seq: u64,
+    payload: Vec<u8>,
+    tag: [u8; 32],
+}
+
+// Authenticated channel established by the Hello/HelloAck handshake. The session key is derived
+// from the pre-shared key and the handshake transcript (protocol version, dataset, parameters and
+// the nonces of both sides), so a handshake tampered with in transit yields a different key. Every
+// frame carries a sequence number, so frames of another session, replayed frames and reordered
+// frames are all rejected.
+pub struct Session {
+    key: [u8; 32],
+    role: Role,
+    send_seq: u64,
+    recv_seq: u64,
+}
+
+pub fn new_nonce() -> Nonce {
+    let mut nonce = [0u8; 32];
+    rand::rng().fill(&mut nonce);
+    nonce
+}
+
+pub fn load_psk(path: &str) -> Result<Vec<u8>> {
+    let psk = fs::read(path).map_err(|err| Error::io(path, err))?;
+    if psk.len() < MIN_PSK_LEN {
+        return Err(Error::format(path, &format!("pre-shared key of {} bytes, expected at least {}", psk.len(), MIN_PSK_LEN)));
+    }
+    Ok(psk)
+}
+
+fn new_mac(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+impl Session {
+    pub fn new(psk: &[u8], dataset: &str, params: &MultiBitPBSParameters, client_nonce: &Nonce, server_nonce: &Nonce, role: Role) -> Self {
+        // Dataset names are compared case-insensitively in the handshake, both sides must agree here
+        let dataset = dataset.to_ascii_lowercase();
+        let params = bincode::serialize(params).expect("parameters are serializable");
+        let mut mac = new_mac(psk);
+        mac.update(b"SecBioAuth session");
+        mac.update(&PROTOCOL_VERSION.to_be_bytes());
+        mac.update(&(dataset.len() as u64).to_be_bytes());
+        mac.update(dataset.as_bytes());
+        mac.update(&(params.len() as u64).to_be_bytes());
+        mac.update(&params);
+        mac.update(client_nonce);
+        mac.update(server_nonce);
+        Session {
+            key: mac.finalize().into_bytes().into(),
+            role,
+            send_seq: 0,
+            recv_seq: 0,
+        }
+    }
+
+    fn tag(&self, direction: u8, seq: u64, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = new_mac(&self.key);
+        mac.update(&[direction]);
+        mac.update(&seq.to_be_bytes());
+        mac.update(payload);
+        mac
+    }
+
+    pub fn send<W: Write>(&mut self, stream: &mut W, message: &Message) -> Result<()> {
+        let payload = bincode::serialize(message).map_err(|err| Error::format("message", &err.to_string()))?;
+        let tag = self.tag(self.role.direction(), self.send_seq, &payload).finalize().into_bytes().into();
+        let frame = AuthenticatedFrame { seq: self.send_seq, payload, tag };
+        let bytes = bincode::serialize(&frame).map_err(|err| Error::format("frame", &err.to_string()))?;
+        write_frame(stream, &bytes)?;
+        self.send_seq += 1;
+        Ok(())
+    }
+
+    // Returns None if the peer closed the connection between two frames
+    pub fn receive<R: Read>(&mut self, stream: &mut R) -> Result<Option<Message>> {
+        let Some(bytes) = read_frame(stream)? else {
+            return Ok(None);
+        };
+        let frame: AuthenticatedFrame = bincode::deserialize(&bytes).map_err(|err| Error::format("frame", &err.to_string()))?;
+        self.tag(self.role.peer().direction(), frame.seq, &frame.payload)
+            .verify_slice(&frame.tag)
+            .map_err(|_| Error::Protocol("frame authentication failed".to_string()))?;
+        if frame.seq != self.recv_seq {
+            return Err(Error::Protocol(format!("replayed or reordered frame: expected sequence number {}, got {}", self.recv_seq, frame.seq)));
+        }
+        self.recv_seq += 1;
+        bincode::deserialize(&frame.payload)
+            .map(Some)
+            .map_err(|err| Error::format("message", &err.to_string()))
+    }
+
+    pub fn expect<R: Read>(&mut self, stream: &mut R) -> Result<Message> {
+        match self.receive(stream)? {
+            Some(Message::Error { message }) => Err(Error::Protocol(format!("peer reported: {}", message))),
+            Some(message) => Ok(message),
+            None => Err(Error::Protocol("connection closed".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ciphertexts::Decision;
+    use crate::common::tfhe_utils::get_params_multi_bit_gpu;
+
+    const PSK: &[u8] = &[7u8; 32];
+
+    fn pair(client_nonce: &Nonce, server_nonce: &Nonce) -> (Session, Session) {
+        let params = get_params_multi_bit_gpu(4).unwrap();
+        (
+            Session::new(PSK, "PUT", &params, client_nonce, server_nonce, Role::Client),
+            Session::new(PSK, "put", &params, client_nonce, server_nonce, Role::Server),
+        )
+    }
+
+    fn report(decision: Decision) -> Message {
+        Message::DecisionReport { decision }
+    }
+
+    fn frame(session: &mut Session, message: &Message) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        session.send(&mut bytes, message).unwrap();
+        bytes
+    }
+
+    fn rejected(session: &mut Session, bytes: &[u8]) -> String {
+        match session.receive(&mut &bytes[..]) {
+            Err(Error::Protocol(message)) => message,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("frame was accepted"),
+        }
+    }
+
+    #[test]
+    fn delivers_frames_in_order() {
+        let (mut client, mut server) = pair(&[1; 32], &[2; 32]);
+        for decision in [Decision::Accept, Decision::Reject] {
+            let bytes = frame(&mut client, &report(decision));
+            assert!(matches!(server.receive(&mut &bytes[..]).unwrap(), Some(Message::DecisionReport { decision: d }) if d == decision));
+        }
+        let bytes = frame(&mut server, &report(Decision::StepUp));
+        assert!(matches!(client.receive(&mut &bytes[..]).unwrap(), Some(Message::DecisionReport { decision: Decision::StepUp })));
+    }
+
+    #[test]
+    fn rejects_tampered_frames() {
+        let (mut client, mut server) = pair(&[1; 32], &[2; 32]);
+        let mut bytes = frame(&mut client, &report(Decision::Reject));
+        // The encoded decision sits right before the 32 byte tag
+        let last = bytes.len() - 33;
+        bytes[last] ^= 1;
+        assert!(rejected(&mut server, &bytes).contains("authentication failed"));
+    }
+
+    #[test]
+    fn rejects_frames_of_another_session() {
+        let (mut client, _) = pair(&[1; 32], &[2; 32]);
+        let (_, mut server) = pair(&[1; 32], &[3; 32]);
+        let bytes = frame(&mut client, &report(Decision::Accept));
+        assert!(rejected(&mut server, &bytes).contains("authentication failed"));
+    }
+
+    #[test]
+    fn binds_the_handshake_transcript() {
+        let params = get_params_multi_bit_gpu(4).unwrap();
+        let mut client = Session::new(PSK, "PUT", &params, &[1; 32], &[2; 32], Role::Client);
+        let bytes = frame(&mut client, &report(Decision::Accept));
+        let mut other_dataset = Session::new(PSK, "FVC", &params, &[1; 32], &[2; 32], Role::Server);
+        assert!(rejected(&mut other_dataset, &bytes).contains("authentication failed"));
+        let mut other_params = Session::new(PSK, "PUT", &get_params_multi_bit_gpu(8).unwrap(), &[1; 32], &[2; 32], Role::Server);
+        assert!(rejected(&mut other_params, &bytes).contains("authentication failed"));
+    }
+
+    #[test]
+    fn rejects_replayed_frames() {
+        let (mut client, mut server) = pair(&[1; 32], &[2; 32]);
+        let bytes = frame(&mut client, &report(Decision::Accept));
+        server.receive(&mut &bytes[..]).unwrap();
+        assert!(rejected(&mut server, &bytes).contains("replayed or reordered"));
+    }
+
+    #[test]
+    fn rejects_reordered_frames() {
+        let (mut client, mut server) = pair(&[1; 32], &[2; 32]);
+        let _first = frame(&mut client, &report(Decision::Accept));
+        let second = frame(&mut client, &report(Decision::Reject));
+        assert!(rejected(&mut server, &second).contains("expected sequence number 0, got 1"));
+    }
+
+    #[test]
+    fn rejects_reflected_frames() {
+        let (_, mut server) = pair(&[1; 32], &[2; 32]);
+        let bytes = frame(&mut server, &report(Decision::Accept));
+        assert!(rejected(&mut server, &bytes).contains("authentication failed"));
+    }
+}